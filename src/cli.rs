@@ -0,0 +1,260 @@
+pub const USAGE: &str = "Usage: z.exe [command] [options] <source>
+
+Commands:
+    tokens    tokenize the source
+    parse     tokenize and parse the source
+    clone     run the parser and the clone pass
+    deref     run the clone pass and resolve every reference
+    dot       resolve every reference and draw the program for Graphviz
+    type      resolve every reference and check the declared types
+    check     run every stage and only report diagnostics
+    run       run every stage and write every dump (default)
+    watch     like run, recompiling whenever a source under <source> is saved
+    fmt       rewrite <source>, or every source under it, in the canonical layout
+
+Options:
+    --root <name>       root namespace of the keychain, defaults to the file name
+                        (with watch on a directory it prefixes every module)
+    --path <dir>        also look for imported modules in <dir>, may be repeated
+    --out-dir <dir>     write artifacts into <dir> instead of next to the source
+    --shadowing         let a later definition of a key replace an earlier one
+    --format <format>   how dumps are written: debug (default), json or sexpr,
+                        json and sexpr dumps get a .json or .sexpr suffix
+    --sorted            dump maps ordered by key instead of in source order
+    --stdout            print dumps and diagnostics instead of writing files
+    --check             with fmt, list the sources that are not formatted
+                        and fail instead of rewriting them
+    --emit <dumps>      comma separated dumps to produce: tokens, 1, 2, 3, 4, dot
+                        (1 is .1.nmap/.1.emap/.1.tmap, 2 is the .2.* set,
+                        3 is the resolved .3.emap, 4 is the typed .4.emap,
+                        dot is the .dot drawing)";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Tokens,
+    Parse,
+    Clone,
+    Deref,
+    Dot,
+    Type,
+    Check,
+    Run,
+    Watch,
+    Fmt,
+}
+
+// stage dumps that can be written next to the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dump {
+    Tokens,
+    Parse,
+    Clone,
+    Deref,
+    Dot,
+    Type,
+}
+
+impl Dump {
+    // position of the stage that produces the dump
+    fn stage(&self) -> usize {
+        match self {
+            Dump::Tokens => 0,
+            Dump::Parse => 1,
+            Dump::Clone => 2,
+            Dump::Deref | Dump::Dot => 3,
+            Dump::Type => 4,
+        }
+    }
+}
+
+// how stage dumps are serialized
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Debug,
+    Json,
+    Sexpr,
+}
+
+impl Format {
+    // appended to the artifact suffix
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Debug => "",
+            Format::Json => ".json",
+            Format::Sexpr => ".sexpr",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command: Command,
+    pub source: String,
+    pub root: Option<String>,
+    pub path: Vec<String>,
+    pub out_dir: Option<String>,
+    pub shadowing: bool,
+    pub format: Format,
+    pub sorted: bool,
+    pub stdout: bool,
+    pub check: bool,
+    pub emit: Vec<Dump>,
+}
+
+impl Options {
+    // whether the command runs far enough to produce the dump
+    pub fn runs(&self, dump: Dump) -> bool {
+        let last = match self.command {
+            Command::Tokens => Dump::Tokens,
+            Command::Parse => Dump::Parse,
+            Command::Clone => Dump::Clone,
+            Command::Deref | Command::Dot => Dump::Deref,
+            Command::Fmt => return false,
+            _ => Dump::Type,
+        };
+        dump.stage() <= last.stage()
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let mut args = args.iter().peekable();
+
+    let command = match args.peek().map(|a| a.as_str()) {
+        Some("tokens") => Some(Command::Tokens),
+        Some("parse") => Some(Command::Parse),
+        Some("clone") => Some(Command::Clone),
+        Some("deref") => Some(Command::Deref),
+        Some("dot") => Some(Command::Dot),
+        Some("type") => Some(Command::Type),
+        Some("check") => Some(Command::Check),
+        Some("run") => Some(Command::Run),
+        Some("watch") => Some(Command::Watch),
+        Some("fmt") => Some(Command::Fmt),
+        _ => None,
+    };
+    // a bare source runs every stage, as z always has
+    let command = match command {
+        Some(command) => {
+            args.next();
+            command
+        }
+        None => Command::Run,
+    };
+
+    let mut source = None;
+    let mut root = None;
+    let mut path = Vec::new();
+    let mut out_dir = None;
+    let mut shadowing = false;
+    let mut format = Format::Debug;
+    let mut sorted = false;
+    let mut stdout = false;
+    let mut check = false;
+    let mut emit = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out-dir" => match args.next() {
+                Some(dir) => out_dir = Some(dir.clone()),
+                None => return Err("--out-dir expects a directory".to_string()),
+            },
+            "--root" => match args.next() {
+                Some(name) if valid_root(name) => root = Some(name.clone()),
+                Some(name) => return Err(format!("'{}' is not a valid root name", name)),
+                None => return Err("--root expects a name".to_string()),
+            },
+            "--path" => match args.next() {
+                Some(dir) => path.push(dir.clone()),
+                None => return Err("--path expects a directory".to_string()),
+            },
+            "--shadowing" => shadowing = true,
+            "--format" => match args.next().map(|f| f.as_str()) {
+                Some("debug") => format = Format::Debug,
+                Some("json") => format = Format::Json,
+                Some("sexpr") => format = Format::Sexpr,
+                Some(name) => return Err(format!("unknown format '{}'", name)),
+                None => return Err("--format expects debug, json or sexpr".to_string()),
+            },
+            "--sorted" => sorted = true,
+            "--stdout" => stdout = true,
+            "--check" => check = true,
+            "--emit" => match args.next() {
+                Some(list) => emit = Some(dumps(list)?),
+                None => return Err("--emit expects a list of dumps".to_string()),
+            },
+            flag if flag.starts_with("--") => {
+                return Err(format!("unknown option '{}'", flag));
+            }
+            positional => {
+                if source.is_some() {
+                    return Err(format!("unexpected argument '{}'", positional));
+                }
+                source = Some(positional.to_string());
+            }
+        }
+    }
+
+    let source = match source {
+        Some(source) => source,
+        None => return Err("missing <source>".to_string()),
+    };
+
+    let emit = match emit {
+        Some(emit) => emit,
+        None => match command {
+            Command::Tokens => vec![Dump::Tokens],
+            Command::Parse => vec![Dump::Parse],
+            Command::Clone => vec![Dump::Clone],
+            Command::Deref => vec![Dump::Deref],
+            Command::Dot => vec![Dump::Dot],
+            Command::Type => vec![Dump::Type],
+            Command::Check | Command::Fmt => Vec::new(),
+            Command::Run | Command::Watch => {
+                vec![Dump::Tokens, Dump::Parse, Dump::Clone, Dump::Deref, Dump::Type]
+            }
+        },
+    };
+
+    if stdout && command == Command::Watch {
+        return Err("watch writes files, --stdout is not supported".to_string());
+    }
+    if check && command != Command::Fmt {
+        return Err("--check is only supported by fmt".to_string());
+    }
+
+    Ok(Options {
+        command,
+        source,
+        root,
+        path,
+        out_dir,
+        shadowing,
+        format,
+        sorted,
+        stdout,
+        check,
+        emit,
+    })
+}
+
+// dotted segments, none of them empty or containing whitespace
+fn valid_root(name: &str) -> bool {
+    name.split('.')
+        .all(|segment| !segment.is_empty() && !segment.contains(char::is_whitespace))
+}
+
+fn dumps(list: &str) -> Result<Vec<Dump>, String> {
+    let mut emit = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        emit.push(match name {
+            "tokens" => Dump::Tokens,
+            "1" => Dump::Parse,
+            "2" => Dump::Clone,
+            "3" => Dump::Deref,
+            "4" => Dump::Type,
+            "dot" => Dump::Dot,
+            _ => return Err(format!("unknown dump '{}'", name)),
+        });
+    }
+    Ok(emit)
+}
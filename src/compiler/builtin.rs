@@ -0,0 +1,71 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+	Arithmetic,
+	Comparison,
+	Stack,
+	Array,
+	Control,
+}
+
+// how many values an operator takes from the stack and how many it leaves
+#[derive(Debug, Clone, Copy)]
+pub struct Operator {
+	pub name: &'static str,
+	pub class: Class,
+	pub inputs: usize,
+	pub outputs: usize,
+}
+
+const fn op(name: &'static str, class: Class, inputs: usize, outputs: usize) -> Operator {
+	Operator {
+		name,
+		class,
+		inputs,
+		outputs,
+	}
+}
+
+// words every program can use without defining them
+pub const OPERATORS: [Operator; 28] = [
+	// arithmetic
+	op("+", Class::Arithmetic, 2, 1),
+	op("-", Class::Arithmetic, 2, 1),
+	op("*", Class::Arithmetic, 2, 1),
+	op("×", Class::Arithmetic, 2, 1),
+	op("/", Class::Arithmetic, 2, 1),
+	op("÷", Class::Arithmetic, 2, 1),
+	op("//", Class::Arithmetic, 2, 1),
+	op("%", Class::Arithmetic, 2, 1),
+	op("^", Class::Arithmetic, 2, 1),
+	op("--", Class::Arithmetic, 1, 1),
+	op("!!", Class::Arithmetic, 1, 1),
+	op("~", Class::Arithmetic, 1, 1),
+	// comparison
+	op("=", Class::Comparison, 2, 1),
+	op("!=", Class::Comparison, 2, 1),
+	op("<", Class::Comparison, 2, 1),
+	op(">", Class::Comparison, 2, 1),
+	op("<=", Class::Comparison, 2, 1),
+	op(">=", Class::Comparison, 2, 1),
+	// stack
+	op("dup", Class::Stack, 1, 2),
+	op("swap", Class::Stack, 2, 2),
+	op("drop", Class::Stack, 1, 0),
+	op("over", Class::Stack, 2, 3),
+	// arrays, `[1 2 3] (+) fold`
+	op("fold", Class::Array, 2, 1),
+	op("scan", Class::Array, 2, 1),
+	op("iota", Class::Array, 1, 1),
+	op("map", Class::Array, 2, 1),
+	// control, `condition then else if`
+	op("if", Class::Control, 3, 1),
+	op("is", Class::Control, 2, 1),
+];
+
+pub fn defined(name: &str) -> bool {
+	operator(name).is_some()
+}
+
+pub fn operator(name: &str) -> Option<&'static Operator> {
+	OPERATORS.iter().find(|o| o.name == name)
+}
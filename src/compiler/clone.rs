@@ -1,181 +1,282 @@
-use super::parser::{EMap, Element, Index, NMap, Network, Node, TMap};
-// use std::collections::HashMap;
+use super::diagnostic::{self, Diagnostic};
+use super::parser::{self, EMap, Element, Index, Kind, NMap, Node, TMap};
+use super::tokenizer::Span;
+use std::collections::HashSet;
 
-// NOTE: this doesn't cover the case of punning and cloning at the same time
-// to solve this, the cloner must search for stacks for puns that actually reference graph points (instead of stack points)
+// A pun of a graph point is a clone of that graph, so puns are looked up before
+// any graph is expanded. Puns of stack points are left to the deref pass.
 
 struct State {
 	nmap: NMap,
 	emap: EMap,
 	tmap: TMap,
+	// graphs whose copies have been expanded, or are being expanded
+	expanded: HashSet<Index>,
+	// graphs being expanded, each with the span and text of the step to the next one
+	chain: Vec<(Index, Span, String)>,
+	// clone source and target while copying, references to the source are moved to the target
+	rebind: Option<(Index, Index)>,
+	diagnostics: Vec<Diagnostic>,
 }
 
-fn last_point(key: &String) -> String {
-	key.split(".")
-		.map(str::to_string)
-		.collect::<Vec<String>>()
-		.last()
-		.unwrap()
-		.clone()
+fn is_graph(node: &Node) -> bool {
+	*node == Node::Graph || *node == Node::Clone
 }
 
-fn point_path(key: &String) -> Vec<String> {
-	let mut k = key.split(".").map(str::to_string).collect::<Vec<String>>();
-	k.pop();
-	k
-}
-
-fn swap_point(keychain: &String, key: &String) -> String {
-	let mut k = point_path(keychain);
-	k.push(key.clone());
-	k.join(".")
-}
+pub fn parser(nmap: NMap, emap: EMap, tmap: TMap) -> (NMap, EMap, TMap, Vec<Diagnostic>) {
+	let mut state = State {
+		nmap,
+		emap,
+		tmap,
+		expanded: HashSet::new(),
+		chain: Vec::new(),
+		rebind: None,
+		diagnostics: Vec::new(),
+	};
 
-// swap second to last name in key chain
-fn swap_graph(keychain: &String, key: &String) -> String {
-	let mut k = keychain
-		.split(".")
-		.map(str::to_string)
-		.collect::<Vec<String>>();
-	let point = k.pop().unwrap();
-	k.pop();
-	k.push(key.clone());
-	k.push(point);
-	k.join(".")
-}
-
-fn sub_key_match(key_a: &String, key_b: &String) -> bool {
-	last_point(key_a) == *key_b
-}
-
-fn contains(keys: &Vec<String>, key: &String) -> bool {
-	let key = last_point(key);
-	for k in keys {
-		if sub_key_match(&k, &key) {
-			return true;
-		}
+	let puns: Vec<String> = state
+		.emap
+		.iter()
+		.filter(|(_, e)| e.body.len() == 1 && e.body[0].kind == Kind::Pun)
+		.map(|(k, _)| k.clone())
+		.collect();
+	for pun in puns {
+		state.pun(&pun);
 	}
-	false
-}
 
-pub fn parser(nmap: NMap, emap: EMap, tmap: TMap) -> Result<(NMap, EMap, TMap), String> {
-	let mut state = State { nmap, emap, tmap };
-
-	let keys: Vec<String> = state.nmap.keys().cloned().collect();
-	let vals: Vec<Network> = state.nmap.values().cloned().collect();
-	for (i, key) in keys.iter().enumerate() {
-		if vals[i].node == Node::Clone {
-			state.begin_clone(key.clone())?;
-		}
+	let graphs: Vec<Index> = (0..state.nmap.len())
+		.filter(|i| is_graph(&state.nmap.at(*i).node))
+		.collect();
+	for graph in graphs {
+		state.expand(graph);
 	}
 
-	Ok((state.nmap, state.emap, state.tmap))
+	(state.nmap, state.emap, state.tmap, state.diagnostics)
 }
 
 impl State {
-	fn clone(
-		&mut self,
-		source_key: &String,
-		target_key: &String,
-	) -> Result<String, String> {
-		let mut val = self.nmap.get(source_key).unwrap().clone();
-
-		let new_key = [target_key.clone(), last_point(source_key)].join(".");
-
-		match val.node {
-			Node::Clone => {
-				self.begin_clone(source_key.clone())?;
-				// let val = self.nmap.get(source_key).unwrap().clone();
-				self.clone(source_key, target_key)?;
-				dbg!("hi");
+	// copy the networks of every clone source into the graph, then do the same
+	// for its subgraphs
+	fn expand(&mut self, key: Index) {
+		if !self.expanded.insert(key) {
+			return;
+		}
+		let graph = self.nmap.at(key);
+		let (meta, node, parent, local) = (graph.meta, graph.node.clone(), graph.parent, graph.keys.clone());
+		let (module, name, copies) = (graph.module.clone(), graph.name.clone(), graph.copy.clone());
+		let mut sources: Vec<(&String, Index)> = Vec::new();
+
+		for copy in &copies {
+			let origin = match parser::lookup(&self.nmap, parent, copy) {
+				Some(origin) => origin,
+				None => {
+					let error = Diagnostic::error(
+						diagnostic::UNDEFINED,
+						format!("{} is undefined", copy),
+						meta,
+					)
+					.label(format!("{} clones {}", name, copy))
+					.in_module(&module);
+					self.diagnostics.push(error);
+					continue;
+				}
+			};
+			let source = self.nmap.at(origin);
+			if !is_graph(&source.node) {
+				let error = Diagnostic::error(
+					diagnostic::CLONE_POINT,
+					format!("{} is a point, only graphs can be cloned", copy),
+					meta,
+				)
+				.secondary(source.meta, format!("{} is defined here", copy))
+				.in_module(&module);
+				self.diagnostics.push(error);
+				continue;
 			}
-			Node::Point => {
-				val.path = new_key.clone();
-				self.nmap.insert(new_key.clone(), val);
-				let element = self.emap.get(source_key).unwrap().clone();
-				self.emap.insert(new_key.clone(), element);
+			let step = format!("{} clones {}", name, copy);
+			if let Some(start) = self.cycle(key, origin) {
+				self.report_cycle(start, key, origin, meta, step);
+				continue;
+			}
+			self.chain.push((key, meta, step));
+			self.expand(origin);
+			self.chain.pop();
+
+			// locally defined keys override copied ones
+			self.rebind = Some((origin, key));
+			for point in self.nmap.at(origin).keys.clone() {
+				if parser::child(&self.nmap, key, &self.nmap.at(point).name).is_none() {
+					self.clone(point, key);
+				}
 			}
-			Node::Graph => {
-				val.path = new_key.clone();
-				let mut new_points: Vec<String> = Vec::new();
-				for point in &val.keys {
-					// dbg!(point);
-					new_points.push(self.clone(point, &new_key)?);
+			self.rebind = None;
+			// a copy of a typed graph is typed the same unless it declares its own
+			let (source_key, target_key) = (self.nmap.key(origin), self.nmap.key(key));
+			if !self.tmap.contains_key(target_key) {
+				if let Some(typ) = self.tmap.get(source_key).cloned() {
+					self.tmap.insert(target_key.clone(), typ);
 				}
-				// dbg!(new_key);
-				// panic!("hi");
-				val.keys = new_points;
-				self.nmap.insert(new_key.clone(), val);
 			}
+			sources.push((copy, origin));
 		}
-		Ok(new_key)
-		// unimplemented!()
-	}
-	fn begin_clone(&mut self, key: String) -> Result<Index, String> {
-		let mut clone = self.nmap.get(&key).unwrap().clone();
-		// let clone_point = last_point(&clone.key);
-		let mut keychain: Vec<String> = key.split(".").map(str::to_string).collect();
-		// let mut refchain: Vec<String> = clone.text.split(".").map(str::to_string).collect();
-		let mut refchain: Vec<String> = Vec::new();
-		// keychain.pop();
-		// keychain.append(&mut refchain);
-
-		// dbg!(&keychain);
-
-		// let origin = self.lookup(&mut keychain)?;
-		// if origin.node == Node::Clone {
-		// 	self.begin_clone(origin.key.clone())?;
-		// }
-		// // dbg!();
-		// let origin = self.lookup(&mut keychain)?;
-
-		let origin = self.lookup(&keychain, &refchain)?;
-		if origin.node == Node::Clone {
-			self.begin_clone(origin.path.clone())?;
+
+		// `source { overrides }` may only replace keys its sources have
+		if node == Node::Clone {
+			for k in local {
+				let network = self.nmap.at(k);
+				if sources.is_empty()
+					|| sources.iter().any(|(_, s)| parser::child(&self.nmap, *s, &network.name).is_some())
+				{
+					continue;
+				}
+				let names: Vec<&str> = sources.iter().map(|(copy, _)| copy.as_str()).collect();
+				let error = Diagnostic::error(
+					diagnostic::UNKNOWN_OVERRIDE,
+					format!("{} is not a key of {}", network.name, names.join(" or ")),
+					network.meta,
+				)
+				.label("overrides nothing".to_string())
+				.in_module(&network.module);
+				self.diagnostics.push(error);
+			}
+			self.nmap.at_mut(key).node = Node::Graph;
 		}
-		let origin = self.lookup(&keychain, &refchain)?;
-		// dbg!(origin);
-		// panic!();
-
-		let mut points_to_clone: Vec<String> = Vec::new();
-		for op in &origin.keys {
-			if !contains(&clone.keys, op) {
-				points_to_clone.push(op.clone());
+
+		for k in self.nmap.at(key).keys.clone() {
+			if is_graph(&self.nmap.at(k).node) {
+				let step = format!("{} contains {}", name, self.nmap.at(k).name);
+				self.chain.push((key, meta, step));
+				self.expand(k);
+				self.chain.pop();
 			}
 		}
+	}
+
+	// where the chain of graphs being expanded reaches the origin, if it does
+	fn cycle(&self, key: Index, origin: Index) -> Option<usize> {
+		if origin == key {
+			return Some(self.chain.len());
+		}
+		self.chain.iter().position(|(k, _, _)| *k == origin)
+	}
+
+	fn report_cycle(&mut self, start: usize, key: Index, origin: Index, span: Span, step: String) {
+		let mut path: Vec<&str> = self.chain[start..].iter().map(|(k, _, _)| self.nmap.key(*k).as_str()).collect();
+		path.push(self.nmap.key(key));
+		path.push(self.nmap.key(origin));
+
+		let mut error = Diagnostic::error(
+			diagnostic::CLONE_CYCLE,
+			format!("clone cycle: {}", path.join(" -> ")),
+			span,
+		)
+		.label(step);
+		for (_, span, step) in &self.chain[start..] {
+			error = error.secondary(*span, step.clone());
+		}
+		let error = error
+			.note("a graph cannot contain a copy of itself".to_string())
+			.in_module(&self.nmap.at(key).module);
+		self.diagnostics.push(error);
+	}
+
+	// turn the pun into a graph that clones the graph it names
+	fn pun(&mut self, key: &str) {
+		let text = self.emap.get(key).unwrap().body[0].text.clone();
+		let index = self.nmap.index(key).unwrap();
+		let outside = self.nmap.at(index).parent.and_then(|p| self.nmap.at(p).parent);
+		let origin = match parser::lookup(&self.nmap, outside, &text) {
+			Some(origin) => origin,
+			None => return,
+		};
+		if !is_graph(&self.nmap.at(origin).node) {
+			return;
+		}
+		self.emap.remove(key);
+		// absolute, a lookup from inside the pun would find the pun itself
+		let copy = self.nmap.key(origin).clone();
+		let network = self.nmap.at_mut(index);
+		network.node = Node::Graph;
+		network.copy = vec![copy];
+	}
 
-		dbg!(&points_to_clone);
+	// deep copy a network and its elements into the target graph
+	fn clone(&mut self, source: Index, target: Index) -> Index {
+		let mut val = self.nmap.at(source).clone();
+		let source_key = self.nmap.key(source).clone();
+		let new_key = format!("{}.{}", self.nmap.key(target), val.name);
 
-		for point in points_to_clone {
-			clone.keys.push(self.clone(&point, &clone.path)?);
+		if let Some(element) = self.emap.get(&source_key) {
+			let mut element = element.clone();
+			self.rebind_element(&mut element, val.parent);
+			self.emap.insert(new_key.clone(), element);
 		}
+		if let Some(typ) = self.tmap.get(&source_key) {
+			self.tmap.insert(new_key.clone(), typ.clone());
+		}
+
+		let points = std::mem::take(&mut val.keys);
+		val.path = new_key.clone();
+		val.parent = Some(target);
+		let index = self.nmap.push(new_key, val);
+		self.nmap.at_mut(target).keys.push(index);
 
-		clone.node = Node::Graph;
-		self.nmap.insert(key, clone);
+		if is_graph(&self.nmap.at(index).node) {
+			// the source was expanded already, its copy list is kept as a record
+			self.expanded.insert(index);
+			for point in points {
+				self.clone(point, index);
+			}
+		}
 
-		// dbg!(key);
-		Ok(0)
+		index
 	}
 
-	fn lookup(
-		&mut self,
-		keychain: &Vec<String>,
-		refchain: &Vec<String>,
-	) -> Result<Network, String> {
-		let key = [keychain.join("."), refchain.join(".")].join(".");
-		dbg!(&key);
-		// panic!();
-		match self.nmap.get(&key) {
-			Some(v) => Ok(v.clone()),
-			None => {
-				if keychain.len() > 1 {
-					let mut keychain = keychain.clone();
-					keychain.swap_remove(keychain.len() - 1);
-					self.lookup(&keychain, refchain)
-				} else {
-					Err(format!("{} is undefined", keychain[0]))
-				}
+	// A reference that resolved to the clone source from the copied element now
+	// names the target, so the copy reads the overrides instead of the originals.
+	// Keys of the source stay relative, anything else it named from where it was
+	// written is made absolute, the target sits in another scope.
+	fn rebind_element(&self, element: &mut Element, scope: Option<Index>) {
+		let (origin, target) = match self.rebind {
+			Some(rebind) => rebind,
+			None => return,
+		};
+		let outside = scope.and_then(|s| self.nmap.at(s).parent);
+		let lookup = match element.kind {
+			Kind::Term => Some(scope),
+			Kind::Pun => Some(outside),
+			_ => None,
+		};
+		if let Some((index, length)) = lookup.and_then(|scope| self.head(scope, &element.text)) {
+			let rest = &element.text[length..];
+			if index == origin {
+				element.text = format!("{}{}", self.nmap.at(target).name, rest);
+			} else if !self.within(index, origin) {
+				element.text = format!("{}{}", self.nmap.key(index), rest);
 			}
 		}
+		for child in element.head.iter_mut().chain(element.body.iter_mut()) {
+			self.rebind_element(child, scope);
+		}
+	}
+
+	// the key the head of a reference names from the scope, with the length of the head
+	fn head(&self, scope: Option<Index>, text: &str) -> Option<(Index, usize)> {
+		let segments: Vec<&str> = text.split('.').collect();
+		(1..=segments.len()).rev().find_map(|length| {
+			let head = segments[..length].join(".");
+			let name = parser::name(&head)?;
+			parser::find(&self.nmap, scope, &name).map(|index| (index, head.len()))
+		})
+	}
+
+	fn within(&self, mut index: Index, graph: Index) -> bool {
+		while index != graph {
+			index = match self.nmap.at(index).parent {
+				Some(parent) => parent,
+				None => return false,
+			};
+		}
+		true
 	}
 }
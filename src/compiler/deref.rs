@@ -1,188 +1,118 @@
-use super::parser::{Element, Env, Index, RvES, RES};
-use std::collections::HashMap;
+use super::builtin;
+use super::diagnostic::{self, Diagnostic};
+use super::parser::{self, EMap, Element, Index, Kind, NMap, Node, TMap};
+use super::tokenizer::Span;
+use std::collections::HashSet;
 
 struct State {
-	points1: Vec<Element>,
-	// points2: Vec<Element>,
+	nmap: NMap,
+	// clones share the spans of their source, each bad reference is reported once
+	reported: HashSet<(String, usize, usize, String)>,
+	diagnostics: Vec<Diagnostic>,
 }
 
-pub fn parser(points1: Vec<Element>) -> RvES {
-	// let mut points2: Vec<Element> = Vec::with_capacity(points1.len());
-	let mut points2: Vec<Element> = vec![Element::Nothing; points1.len()];
-	let mut state = State { points1 };
-
-	state.dispatch(&mut points2, &HashMap::new(), 0)?;
+// Resolve every term to the absolute path of the point it names. Builtin
+// operators keep their bare name as their path.
+pub fn parser(nmap: NMap, emap: EMap, tmap: TMap) -> (NMap, EMap, TMap, Vec<Diagnostic>) {
+	let mut state = State {
+		nmap,
+		reported: HashSet::new(),
+		diagnostics: Vec::new(),
+	};
+
+	let mut resolved = EMap::new();
+	for (key, element) in &emap {
+		let mut element = element.clone();
+		let scope = state.nmap.index(key).and_then(|i| state.nmap.at(i).parent);
+		state.element(key, scope, &mut element);
+		resolved.insert(key.clone(), element);
+	}
 
-	Ok(points2)
+	(state.nmap, resolved, tmap, state.diagnostics)
 }
 
 impl State {
-	fn dispatch(
-		&mut self,
-		points2: &mut Vec<Element>,
-		env: &Env,
-		index: Index,
-	) -> Result<Index, String> {
-		let el = match self.points1[index].clone() {
-			Element::Graph {
-				keys,
-				env: graph_env,
-			} => {
-				for k in &keys {
-					self.dispatch(points2, &graph_env, *k)?;
-				}
-
-				points2[index] = Element::Graph {
-					env: graph_env.clone(),
-					keys: keys.clone(),
-				};
-			}
-
-			Element::Clone {
-				texts,
-				keys,
-				env: clone_env,
-				index: _,
-			} => {
-				for k in &keys {
-					self.dispatch(points2, &clone_env, *k)?;
-				}
-
-				let index_ = self.deref(env, &texts)?;
-
-				points2[index] = Element::Clone {
-					texts: texts.clone(),
-					index: index_,
-					env: clone_env.clone(),
-					keys: keys.clone(),
-				};
+	fn element(&mut self, key: &String, scope: Option<Index>, element: &mut Element) {
+		if element.kind == Kind::Term {
+			match self.resolve(scope, &element.text, element.meta) {
+				Ok(path) => element.path = path,
+				Err(_) if builtin::defined(&element.text) => element.path = element.text.clone(),
+				Err(error) => self.report(key, element, error),
 			}
-
-			Element::Tuple { body } => {
-				let mut newbody: Vec<Element> = Vec::new();
-				for e in body {
-					newbody.push(self.element(env, &e)?);
-				}
-
-				points2[index] = Element::Tuple { body: newbody };
-			}
-			// e => return Err(format!("Unexpected element: {:?}", e)),
-			e => {
-				// print!("Skipping: {:?}", e);
-				// e.clone()
-				panic!("unexpected element: {:?}", e);
+		}
+		if element.kind == Kind::Pun {
+			// the graph holding the pun defines the name itself, look outside of it
+			let outside = scope.and_then(|s| self.nmap.at(s).parent);
+			match self.resolve(outside, &element.text, element.meta) {
+				Ok(path) => element.path = path,
+				Err(error) => self.report(key, element, error),
 			}
-		};
-		Ok(index)
+		}
+		for child in element.head.iter_mut().chain(element.body.iter_mut()) {
+			self.element(key, scope, child);
+		}
 	}
 
-	fn element(&mut self, env: &Env, element: &Element) -> RES {
-		let el = match element {
-			Element::Ref { texts, index } => {
-				let index = self.deref(env, texts)?;
-
-				Element::Ref {
-					texts: texts.clone(),
-					index,
-				}
-			}
-			Element::Pun { texts, index: _ } => {
-				let parent_index =
-					self.lookup(env, &String::from("(parent)"))?;
-				let index = match &self.points1[parent_index] {
-					Element::Graph { env, keys } => self.deref(env, texts)?,
-					e => panic!("unexpected element: {:?}", e),
-				};
+	fn report(&mut self, key: &str, element: &Element, error: Diagnostic) {
+		let span = element.meta;
+		let module = parser::module(&self.nmap, key).to_string();
+		if self.reported.insert((module.clone(), span.start.offset, span.end.offset, element.text.clone())) {
+			self.diagnostics.push(error.in_module(&module));
+		}
+	}
 
-				Element::Ref {
-					texts: texts.clone(),
-					index,
+	// the head of a dotted reference is found lexically, the rest through graph
+	// keys. A dotted key is tried whole before its segments.
+	#[allow(clippy::result_large_err)]
+	fn resolve(&self, scope: Option<Index>, text: &str, span: Span) -> Result<String, Diagnostic> {
+		let segments: Vec<&str> = text.split('.').collect();
+		let head = (1..=segments.len()).rev().find_map(|length| {
+			let name = parser::name(&segments[..length].join("."))?;
+			parser::find(&self.nmap, scope, &name).map(|index| (index, length))
+		});
+		let (mut index, rest) = match head {
+			Some((index, length)) => (index, &segments[length..]),
+			None => match self.module(&segments) {
+				Some(length) => (self.nmap.index(&segments[..length].join(".")).unwrap(), &segments[length..]),
+				None => {
+					return Err(Diagnostic::error(
+						diagnostic::UNDEFINED_REFERENCE,
+						format!("{} is undefined", segments[0]),
+						span,
+					)
+					.label("not found in this scope".to_string()))
 				}
-			}
-			e => e.clone(),
+			},
 		};
-		Ok(el)
-	}
-}
 
-impl State {
-	fn deref(
-		&self,
-		env: &Env,
-		key_chain: &Vec<String>,
-	) -> Result<Index, String> {
-		let mut env: &Env = env;
-		for (i, key) in key_chain.iter().enumerate() {
-			if i == key_chain.len() - 1 {
-				return Ok(self.lookup(env, key)?);
-			} else {
-				let index = self.lookup(env, key)?;
-				env = match &self.points1[index] {
-					Element::Graph { env, keys } => env,
-					e => panic!("Unexpected element: {:?}", e),
-				};
+		for segment in rest {
+			let network = self.nmap.at(index);
+			if network.node == Node::Point {
+				return Err(Diagnostic::error(
+					diagnostic::NOT_A_GRAPH,
+					format!("{} is a point, not a graph", network.name),
+					span,
+				)
+				.label(format!("{} cannot be looked up in a point", segment))
+				.secondary(network.meta, format!("{} is defined here", network.name)));
 			}
-		}
-		Ok(0)
-	}
-
-	fn lookup(&self, env: &Env, key: &String) -> Result<Index, String> {
-		match env.get(key) {
-			Some(i) => Ok(*i),
-			None => {
-				match env.get(&String::from("(parent)")) {
-					None => Err(format!("{} is undefined", key)),
-					Some(i) => {
-						match &self.points1[*i] {
-							Element::Graph { env, keys } => {
-								self.lookup(&env, key)
-							}
-							// Element::Clone { text, env, keys} => {
-							// 	self.lookup(env, key)
-							// }
-							e => panic!("Unexpected element: {:?}", e),
-						}
-					}
+			index = match parser::name(segment).and_then(|name| parser::child(&self.nmap, index, &name)) {
+				Some(index) => index,
+				None => {
+					return Err(Diagnostic::error(
+						diagnostic::UNDEFINED_REFERENCE,
+						format!("{} is not a key of {}", segment, network.name),
+						span,
+					)
+					.label(format!("{} has no key {}", network.name, segment)))
 				}
-			}
+			};
 		}
+		Ok(self.nmap.key(index).clone())
 	}
 
-	// fn clone_elements(
-	// 	&self,
-	// 	points2: &mut Vec<Element>,
-	// 	keys_to_copy: &Vec<Index>,
-	// ) -> Vec<Index> {
-	// 	let mut new_keys: Vec<Index> = Vec::new();
-
-	// 	for index in keys_to_copy {
-	// 		new_keys.push(self.clone_element(points2, *index));
-	// 	}
-
-	// 	new_keys
-	// }
-
-	// fn clone_element(
-	// 	&self,
-	// 	points2: &mut Vec<Element>,
-	// 	key: Index,
-	// ) -> Index {
-	// 	let index = points2.len();
-	// 	let el = &points2[key];
-	// 	let e = match el {
-	// 		Element::Clone { env, keys, texts } => {
-	// 			panic!("fudge");
-	// 		}
-	// 		Element::Graph { env, keys } => {
-	// 			for (k, v) in env {
-
-	// 			}
-	// 		}
-	// 		e => {
-	// 			e.clone()
-	// 		}
-	// 	};
-	// 	points2.push(e);
-	// 	index
-	// }
+	// the longest leading segments naming a module root, as in `library.windowing.frame`
+	fn module(&self, segments: &[&str]) -> Option<usize> {
+		(1..=segments.len()).rev().find(|length| self.nmap.contains_key(&segments[..*length].join(".")))
+	}
 }
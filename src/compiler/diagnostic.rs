@@ -0,0 +1,235 @@
+use super::tokenizer::Span;
+use std::fmt;
+
+// error codes, the hundreds digit names the stage that raised it
+pub const INVALID_CHARACTER: &str = "E0001";
+pub const UNTERMINATED_STRING: &str = "E0002";
+pub const UNEXPECTED_TOKEN: &str = "E0101";
+pub const UNEXPECTED_END: &str = "E0102";
+pub const DUPLICATE_KEY: &str = "E0103";
+pub const INVALID_LITERAL: &str = "E0104";
+pub const UNDEFINED: &str = "E0201";
+pub const IMPORT_CYCLE: &str = "E0202";
+pub const CLONE_POINT: &str = "E0203";
+pub const UNKNOWN_OVERRIDE: &str = "E0204";
+pub const CLONE_CYCLE: &str = "E0205";
+pub const UNDEFINED_REFERENCE: &str = "E0301";
+pub const NOT_A_GRAPH: &str = "E0302";
+pub const TYPE_MISMATCH: &str = "E0401";
+pub const STACK_UNDERFLOW: &str = "E0402";
+pub const LEFTOVER_VALUES: &str = "E0403";
+pub const MISSING_VALUES: &str = "E0404";
+pub const INCOMPATIBLE_WIDTHS: &str = "E0405";
+pub const UNKNOWN_WIDTH: &str = "E0406";
+pub const LITERAL_OVERFLOW: &str = "E0407";
+
+// warning codes
+pub const PRECISION_LOSS: &str = "W0408";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+	Error,
+	Warning,
+	Note,
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Severity::Error => write!(f, "error"),
+			Severity::Warning => write!(f, "warning"),
+			Severity::Note => write!(f, "note"),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+	pub span: Span,
+	pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub code: &'static str,
+	pub message: String,
+	pub primary: Label,
+	pub secondary: Vec<Label>,
+	pub notes: Vec<String>,
+	// keychain path within the module the spans point into, None for the compiled file
+	pub module: Option<String>,
+}
+
+// source text of every module a diagnostic can point into
+pub struct Sources {
+	// root, file name and text, the compiled file first
+	modules: Vec<(String, String, String)>,
+}
+
+impl Sources {
+	pub fn new(root: &str, filename: &str, text: &str) -> Sources {
+		Sources {
+			modules: vec![(root.to_string(), filename.to_string(), text.to_string())],
+		}
+	}
+
+	pub fn add(&mut self, root: &str, filename: &str, text: &str) {
+		self.modules.push((root.to_string(), filename.to_string(), text.to_string()));
+	}
+
+	// the module with the longest root that prefixes the path
+	fn find(&self, module: &Option<String>) -> &(String, String, String) {
+		let path = match module {
+			Some(path) => path,
+			None => return &self.modules[0],
+		};
+		self.modules
+			.iter()
+			.filter(|(root, _, _)| path == root || path.starts_with(&format!("{}.", root)))
+			.max_by_key(|(root, _, _)| root.len())
+			.unwrap_or(&self.modules[0])
+	}
+}
+
+impl Diagnostic {
+	pub fn new(
+		severity: Severity,
+		code: &'static str,
+		message: String,
+		span: Span,
+	) -> Diagnostic {
+		Diagnostic {
+			severity,
+			code,
+			message,
+			primary: Label {
+				span,
+				text: String::new(),
+			},
+			secondary: Vec::new(),
+			notes: Vec::new(),
+			module: None,
+		}
+	}
+
+	pub fn error(code: &'static str, message: String, span: Span) -> Diagnostic {
+		Diagnostic::new(Severity::Error, code, message, span)
+	}
+
+	pub fn warning(code: &'static str, message: String, span: Span) -> Diagnostic {
+		Diagnostic::new(Severity::Warning, code, message, span)
+	}
+
+	// text printed next to the carets of the primary span
+	pub fn label(mut self, text: String) -> Diagnostic {
+		self.primary.text = text;
+		self
+	}
+
+	pub fn secondary(mut self, span: Span, text: String) -> Diagnostic {
+		self.secondary.push(Label { span, text });
+		self
+	}
+
+	pub fn note(mut self, text: String) -> Diagnostic {
+		self.notes.push(text);
+		self
+	}
+
+	pub fn in_module(mut self, path: &str) -> Diagnostic {
+		self.module = Some(path.to_string());
+		self
+	}
+
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+
+	pub fn render(&self, filename: &str, source: &str) -> String {
+		let lines: Vec<&str> = source.split('\n').collect();
+		let mut labels: Vec<(&Label, char)> = vec![(&self.primary, '^')];
+		for label in &self.secondary {
+			labels.push((label, '-'));
+		}
+		labels.sort_by_key(|(label, _)| label.span.start.offset);
+
+		let mut rows: Vec<usize> = labels.iter().map(|(l, _)| l.span.start.row).collect();
+		rows.sort();
+		rows.dedup();
+		let gutter = rows.last().unwrap_or(&0).to_string().len();
+		let pad = " ".repeat(gutter);
+
+		let mut output = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+		output.push_str(&format!(
+			"{}--> {}:{}:{}\n",
+			pad, filename, self.primary.span.start.row, self.primary.span.start.col
+		));
+		output.push_str(&format!("{} |\n", pad));
+
+		let mut previous = 0;
+		for row in rows {
+			if previous != 0 && row > previous + 1 {
+				output.push_str(&format!("{}...\n", pad));
+			}
+			previous = row;
+
+			let line = lines.get(row - 1).unwrap_or(&"").trim_end_matches('\r');
+			output.push_str(&format!("{:>w$} | {}\n", row, expand(line), w = gutter));
+
+			for (label, mark) in labels.iter().filter(|(l, _)| l.span.start.row == row) {
+				let span = label.span;
+				let start = width(line, span.start.col);
+				let end = if span.end.row == row {
+					width(line, span.end.col)
+				} else {
+					width(line, line.chars().count() + 1)
+				};
+				let carets = mark.to_string().repeat(end.saturating_sub(start).max(1));
+				let mut underline = format!("{} | {}{}", pad, " ".repeat(start), carets);
+				if !label.text.is_empty() {
+					underline.push(' ');
+					underline.push_str(&label.text);
+				}
+				output.push_str(&underline);
+				output.push('\n');
+			}
+		}
+
+		if !self.notes.is_empty() {
+			output.push_str(&format!("{} |\n", pad));
+		}
+		for note in &self.notes {
+			output.push_str(&format!("{} = note: {}\n", pad, note));
+		}
+		output
+	}
+}
+
+pub fn render(diagnostics: &[Diagnostic], sources: &Sources) -> String {
+	let mut output = String::new();
+	for diagnostic in diagnostics {
+		let (_, filename, source) = sources.find(&diagnostic.module);
+		output.push_str(&diagnostic.render(filename, source));
+		output.push('\n');
+	}
+	let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+	let warnings = diagnostics.iter().filter(|d| d.severity == Severity::Warning).count();
+	output.push_str(&format!("{} error(s), {} warning(s)\n", errors, warnings));
+	output
+}
+
+const TAB: &str = "    ";
+
+// tabs are expanded so that carets line up with the echoed source
+fn expand(line: &str) -> String {
+	line.replace('\t', TAB)
+}
+
+// display width of a line up to (not including) a 1-based column
+fn width(line: &str, col: usize) -> usize {
+	line.chars()
+		.take(col.saturating_sub(1))
+		.map(|c| if c == '\t' { TAB.len() } else { 1 })
+		.sum()
+}
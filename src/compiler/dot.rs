@@ -0,0 +1,102 @@
+use super::parser::{self, EMap, Element, Index, NMap, Node};
+
+// Graphviz drawing of a resolved program: graphs are clusters, points are
+// nodes, clones are dashed edges between clusters and every point has an edge
+// to each point its stack refers to.
+pub fn graph(root: &str, nmap: &NMap, emap: &EMap) -> String {
+	let mut output = format!("digraph {} {{\n", id(root));
+	output.push_str("\tcompound=true;\n");
+	output.push_str("\tnode [shape=box];\n");
+
+	for i in 0..nmap.len() {
+		if nmap.at(i).parent.is_none() {
+			network(nmap, i, 1, &mut output);
+		}
+	}
+
+	for i in 0..nmap.len() {
+		let network = nmap.at(i);
+		if network.node == Node::Point {
+			continue;
+		}
+		for copy in &network.copy {
+			if let Some(origin) = parser::lookup(nmap, network.parent, copy) {
+				output.push_str(&format!(
+					"\t{} -> {} [style=dashed, ltail={}, lhead={}];\n",
+					id(&anchor(nmap, i)),
+					id(&anchor(nmap, origin)),
+					id(&cluster(i)),
+					id(&cluster(origin)),
+				));
+			}
+		}
+	}
+
+	for (key, element) in emap {
+		let mut paths: Vec<&String> = Vec::new();
+		references(element, &mut paths);
+		for path in paths {
+			let target = match nmap.index(path) {
+				Some(target) => target,
+				// builtins
+				None => continue,
+			};
+			if nmap.at(target).node == Node::Point {
+				output.push_str(&format!("\t{} -> {};\n", id(key), id(path)));
+			} else {
+				output.push_str(&format!(
+					"\t{} -> {} [lhead={}];\n",
+					id(key),
+					id(&anchor(nmap, target)),
+					id(&cluster(target)),
+				));
+			}
+		}
+	}
+
+	output.push_str("}\n");
+	output
+}
+
+fn network(nmap: &NMap, index: Index, depth: usize, output: &mut String) {
+	let tabs = "\t".repeat(depth);
+	let network = nmap.at(index);
+	if network.node == Node::Point {
+		output.push_str(&format!("{}{} [label={}];\n", tabs, id(&network.path), id(&network.name)));
+		return;
+	}
+	output.push_str(&format!("{}subgraph {} {{\n", tabs, id(&cluster(index))));
+	output.push_str(&format!("{}\tlabel={};\n", tabs, id(&network.name)));
+	// edges to and from the graph attach here
+	output.push_str(&format!(
+		"{}\t{} [shape=point, style=invis];\n",
+		tabs,
+		id(&anchor(nmap, index))
+	));
+	for key in &network.keys {
+		self::network(nmap, *key, depth + 1, output);
+	}
+	output.push_str(&format!("{}}}\n", tabs));
+}
+
+// resolved paths in the order the stack mentions them
+fn references<'a>(element: &'a Element, paths: &mut Vec<&'a String>) {
+	if !element.path.is_empty() && !paths.contains(&&element.path) {
+		paths.push(&element.path);
+	}
+	for child in element.head.iter().chain(element.body.iter()) {
+		references(child, paths);
+	}
+}
+
+fn cluster(index: Index) -> String {
+	format!("cluster_{}", index)
+}
+
+fn anchor(nmap: &NMap, index: Index) -> String {
+	format!("{} (graph)", nmap.key(index))
+}
+
+fn id(text: &str) -> String {
+	format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,370 @@
+use super::map::Map;
+use super::parser::{self, Element, Literal, Network};
+use super::tokenizer::{Meta, Span, Token};
+
+// bumped whenever the shape of an exported stage changes
+pub const VERSION: usize = 5;
+
+// The intermediate representations as plain data, written out as JSON or as
+// S-expressions for tools outside of Rust.
+pub enum Value {
+	Null,
+	Number(usize),
+	Float(f64),
+	Text(String),
+	List(Vec<Value>),
+	// fields keep their order
+	Object(Vec<(&'static str, Value)>),
+}
+
+pub trait Export {
+	fn export(&self) -> Value;
+}
+
+// `{"version": 1, "stage": "nmap", "data": ...}`
+pub fn json(stage: &str, data: &dyn Export) -> String {
+	let mut output = String::new();
+	write_json(&document(stage, data), 0, &mut output);
+	output.push('\n');
+	output
+}
+
+// `(:version 1 :stage "nmap" :data ...)`
+pub fn sexpr(stage: &str, data: &dyn Export) -> String {
+	let mut output = String::new();
+	write_sexpr(&document(stage, data), 0, &mut output);
+	output.push('\n');
+	output
+}
+
+fn document(stage: &str, data: &dyn Export) -> Value {
+	Value::Object(vec![
+		("version", Value::Number(VERSION)),
+		("stage", Value::Text(stage.to_string())),
+		("data", data.export()),
+	])
+}
+
+// lists and objects that fit on a line are not broken up
+const WIDTH: usize = 80;
+
+fn indent(depth: usize, output: &mut String) {
+	output.push('\n');
+	output.push_str(&"  ".repeat(depth));
+}
+
+fn flat_json(value: &Value, output: &mut String) {
+	match value {
+		Value::List(items) => {
+			output.push('[');
+			for (i, item) in items.iter().enumerate() {
+				if i > 0 {
+					output.push_str(", ");
+				}
+				flat_json(item, output);
+			}
+			output.push(']');
+		}
+		Value::Object(fields) => {
+			output.push('{');
+			for (i, (name, field)) in fields.iter().enumerate() {
+				if i > 0 {
+					output.push_str(", ");
+				}
+				quote(name, output);
+				output.push_str(": ");
+				flat_json(field, output);
+			}
+			output.push('}');
+		}
+		atom => write_json(atom, 0, output),
+	}
+}
+
+fn write_json(value: &Value, depth: usize, output: &mut String) {
+	if let Value::List(_) | Value::Object(_) = value {
+		let mut flat = String::new();
+		flat_json(value, &mut flat);
+		if depth * 2 + flat.len() <= WIDTH {
+			output.push_str(&flat);
+			return;
+		}
+	}
+	match value {
+		Value::Null => output.push_str("null"),
+		Value::Number(n) => output.push_str(&n.to_string()),
+		Value::Float(f) => output.push_str(&format!("{:?}", f)),
+		Value::Text(text) => quote(text, output),
+		Value::List(items) => {
+			output.push('[');
+			for (i, item) in items.iter().enumerate() {
+				if i > 0 {
+					output.push(',');
+				}
+				indent(depth + 1, output);
+				write_json(item, depth + 1, output);
+			}
+			indent(depth, output);
+			output.push(']');
+		}
+		Value::Object(fields) => {
+			output.push('{');
+			for (i, (name, field)) in fields.iter().enumerate() {
+				if i > 0 {
+					output.push(',');
+				}
+				indent(depth + 1, output);
+				quote(name, output);
+				output.push_str(": ");
+				write_json(field, depth + 1, output);
+			}
+			indent(depth, output);
+			output.push('}');
+		}
+	}
+}
+
+fn flat_sexpr(value: &Value, output: &mut String) {
+	match value {
+		Value::List(items) => {
+			output.push('(');
+			for (i, item) in items.iter().enumerate() {
+				if i > 0 {
+					output.push(' ');
+				}
+				flat_sexpr(item, output);
+			}
+			output.push(')');
+		}
+		Value::Object(fields) => {
+			output.push('(');
+			for (i, (name, field)) in fields.iter().enumerate() {
+				if i > 0 {
+					output.push(' ');
+				}
+				output.push(':');
+				output.push_str(name);
+				output.push(' ');
+				flat_sexpr(field, output);
+			}
+			output.push(')');
+		}
+		atom => write_sexpr(atom, 0, output),
+	}
+}
+
+fn write_sexpr(value: &Value, depth: usize, output: &mut String) {
+	if let Value::List(_) | Value::Object(_) = value {
+		let mut flat = String::new();
+		flat_sexpr(value, &mut flat);
+		if depth * 2 + flat.len() <= WIDTH {
+			output.push_str(&flat);
+			return;
+		}
+	}
+	match value {
+		Value::Null => output.push_str("nil"),
+		Value::Number(n) => output.push_str(&n.to_string()),
+		Value::Float(f) => output.push_str(&format!("{:?}", f)),
+		Value::Text(text) => quote(text, output),
+		Value::List(items) => {
+			output.push('(');
+			for (i, item) in items.iter().enumerate() {
+				if i > 0 {
+					indent(depth + 1, output);
+				}
+				write_sexpr(item, depth + 1, output);
+			}
+			output.push(')');
+		}
+		Value::Object(fields) => {
+			output.push('(');
+			for (i, (name, field)) in fields.iter().enumerate() {
+				if i > 0 {
+					indent(depth + 1, output);
+				}
+				output.push(':');
+				output.push_str(name);
+				output.push(' ');
+				write_sexpr(field, depth + 1, output);
+			}
+			output.push(')');
+		}
+	}
+}
+
+// a string literal both formats accept
+fn quote(text: &str, output: &mut String) {
+	output.push('"');
+	for c in text.chars() {
+		match c {
+			'"' => output.push_str("\\\""),
+			'\\' => output.push_str("\\\\"),
+			'\n' => output.push_str("\\n"),
+			'\r' => output.push_str("\\r"),
+			'\t' => output.push_str("\\t"),
+			c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+			c => output.push(c),
+		}
+	}
+	output.push('"');
+}
+
+fn text(text: &str) -> Value {
+	Value::Text(text.to_string())
+}
+
+fn list<T: Export>(items: &[T]) -> Value {
+	Value::List(items.iter().map(Export::export).collect())
+}
+
+impl Export for Meta {
+	fn export(&self) -> Value {
+		Value::Object(vec![
+			("row", Value::Number(self.row)),
+			("col", Value::Number(self.col)),
+			("offset", Value::Number(self.offset)),
+		])
+	}
+}
+
+impl Export for Span {
+	fn export(&self) -> Value {
+		Value::Object(vec![("start", self.start.export()), ("end", self.end.export())])
+	}
+}
+
+impl Export for Token {
+	fn export(&self) -> Value {
+		Value::Object(vec![
+			("kind", text(&format!("{:?}", self.kind))),
+			("text", text(&self.text)),
+			("span", self.span().export()),
+		])
+	}
+}
+
+impl Export for Vec<Token> {
+	fn export(&self) -> Value {
+		list(self)
+	}
+}
+
+impl Export for Network {
+	fn export(&self) -> Value {
+		let parent = match self.parent {
+			Some(parent) => Value::Number(parent),
+			None => Value::Null,
+		};
+		Value::Object(vec![
+			("path", text(&self.path)),
+			("module", text(&self.module)),
+			("name", text(&self.name)),
+			("node", text(&format!("{:?}", self.node))),
+			("parent", parent),
+			("keys", Value::List(self.keys.iter().map(|k| Value::Number(*k)).collect())),
+			("copy", Value::List(self.copy.iter().map(|c| text(c)).collect())),
+			("span", self.meta.export()),
+		])
+	}
+}
+
+impl Export for Element {
+	fn export(&self) -> Value {
+		Value::Object(vec![
+			("kind", text(&format!("{:?}", self.kind))),
+			("text", text(&self.text)),
+			("path", text(&self.path)),
+			("literal", match &self.literal {
+				Some(literal) => literal.export(),
+				None => Value::Null,
+			}),
+			("type", text(&self.typ.to_string())),
+			("para", Value::Number(self.para)),
+			("span", self.meta.export()),
+			("head", list(&self.head)),
+			("body", list(&self.body)),
+		])
+	}
+}
+
+// strings are a list of their bytes, width is the type of a suffix like `10i32`
+impl Export for Literal {
+	fn export(&self) -> Value {
+		let value = match &self.value {
+			parser::Value::Integer(n) => Value::Number(*n as usize),
+			parser::Value::Decimal(f) => Value::Float(*f),
+			parser::Value::Bytes(bytes) => Value::List(bytes.iter().map(|b| Value::Number(*b as usize)).collect()),
+		};
+		let width = match &self.width {
+			Some(width) => text(&width.to_string()),
+			None => Value::Null,
+		};
+		Value::Object(vec![("value", value), ("width", width)])
+	}
+}
+
+// a list in map order, so that the position of an entry is its Index
+impl<V: Export> Export for Map<V> {
+	fn export(&self) -> Value {
+		Value::List(
+			self.iter()
+				.map(|(key, value)| Value::Object(vec![("key", text(key)), ("value", value.export())]))
+				.collect(),
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::{clone, deref, tokenizer, typer};
+	use super::*;
+
+	// Golden dumps of one stage each. A change to the shape of a dump has to
+	// bump VERSION and rewrite the files in snapshots/ with `z run --root test`.
+	const SOURCE: &str = "a: i32, 1\nb; a\n";
+
+	fn check(stage: &str, data: &dyn Export, expected_json: &str, expected_sexpr: &str) {
+		assert_eq!(json(stage, data), expected_json, "{}.json", stage);
+		assert_eq!(sexpr(stage, data), expected_sexpr, "{}.sexpr", stage);
+	}
+
+	#[test]
+	fn snapshots() {
+		let (tokens, _) = tokenizer::tokenizer(SOURCE);
+		check(
+			"tokens",
+			&tokens,
+			include_str!("snapshots/tokens.json"),
+			include_str!("snapshots/tokens.sexpr"),
+		);
+		let (nmap, emap, tmap, _) = parser::parser("test", tokens, false);
+		check(
+			"1.nmap",
+			&nmap,
+			include_str!("snapshots/1.nmap.json"),
+			include_str!("snapshots/1.nmap.sexpr"),
+		);
+		let (nmap, emap, tmap, _) = clone::parser(nmap, emap, tmap);
+		check(
+			"2.tmap",
+			&tmap,
+			include_str!("snapshots/2.tmap.json"),
+			include_str!("snapshots/2.tmap.sexpr"),
+		);
+		let (nmap, emap, tmap, _) = deref::parser(nmap, emap, tmap);
+		check(
+			"3.emap",
+			&emap,
+			include_str!("snapshots/3.emap.json"),
+			include_str!("snapshots/3.emap.sexpr"),
+		);
+		let (_, emap, _, _) = typer::parser(nmap, emap, tmap);
+		check(
+			"4.emap",
+			&emap,
+			include_str!("snapshots/4.emap.json"),
+			include_str!("snapshots/4.emap.sexpr"),
+		);
+	}
+}
@@ -0,0 +1,132 @@
+use super::diagnostic::Diagnostic;
+use super::parser;
+use super::tokenizer::{self, Kind as TKind, Token};
+
+// Rewrite a source in the canonical layout. Line breaks and comments are kept,
+// indentation becomes one tab per open graph, plus one while a tuple or an
+// array is open, and tokens on a line are spaced the same way everywhere.
+// Sources that do not tokenize or parse cleanly are left alone.
+pub fn format(input: &str) -> Result<String, Vec<Diagnostic>> {
+	let (tokens, mut diagnostics) = tokenizer::trivia(input);
+	let significant: Vec<Token> = tokens.iter().filter(|t| !trivial(t.kind)).cloned().collect();
+	let (_, _, _, mut errors) = parser::parser("", significant, true);
+	diagnostics.append(&mut errors);
+	if diagnostics.iter().any(|d| d.is_error()) {
+		return Err(diagnostics);
+	}
+
+	let mut output = String::new();
+	let mut depth = 0;
+	let mut parens = 0;
+	let mut params = false;
+	let mut previous: Option<TKind> = None;
+	let mut blank = false;
+
+	for line in tokens.split(|t| t.kind == TKind::Newline) {
+		let line: Vec<&Token> = line.iter().filter(|t| t.kind != TKind::Skip).collect();
+		if line.is_empty() {
+			// runs of blank lines become one, leading ones are dropped
+			blank = !output.is_empty();
+			continue;
+		}
+		if blank {
+			output.push('\n');
+			blank = false;
+		}
+
+		// closing brackets at the start of a line sit at the outer level
+		let (mut level, mut open) = (depth, parens);
+		for token in line.iter().take_while(|t| closing(t.kind)) {
+			match token.kind {
+				TKind::BracketRT => level = usize::saturating_sub(level, 1),
+				_ => open = usize::saturating_sub(open, 1),
+			}
+		}
+		let indent = level + if open > 0 { 1 } else { 0 };
+		output.push_str(&"\t".repeat(indent));
+
+		let mut first = true;
+		for token in line {
+			// multiple commas can be used, one is written
+			if token.kind == TKind::Com && previous == Some(TKind::Com) {
+				continue;
+			}
+			if !first && spaced(previous.unwrap(), token.kind, params) {
+				output.push(' ');
+			}
+			first = false;
+			output.push_str(&text(token));
+
+			match token.kind {
+				TKind::Net | TKind::BracketLF => depth += 1,
+				TKind::BracketRT => depth = usize::saturating_sub(depth, 1),
+				TKind::ParenLF | TKind::SquarenLF => parens += 1,
+				TKind::ParenRT | TKind::SquarenRT => parens = usize::saturating_sub(parens, 1),
+				TKind::Post => params = !params,
+				_ => {}
+			}
+			previous = Some(token.kind);
+		}
+		output.push('\n');
+	}
+
+	Ok(output)
+}
+
+fn trivial(kind: TKind) -> bool {
+	kind == TKind::Skip || kind == TKind::Comment || kind == TKind::Newline
+}
+
+fn closing(kind: TKind) -> bool {
+	kind == TKind::BracketRT || kind == TKind::ParenRT || kind == TKind::SquarenRT
+}
+
+// whether a space goes between two tokens on the same line, `params` is set
+// between the bars of a parameter list
+fn spaced(left: TKind, right: TKind, params: bool) -> bool {
+	match (left, right) {
+		(TKind::ParenLF | TKind::SquarenLF, _) => false,
+		(_, TKind::ParenRT | TKind::SquarenRT | TKind::Com) => false,
+		// `|x y|`
+		(TKind::Post, _) if params => false,
+		(_, TKind::Post) if params => false,
+		_ => true,
+	}
+}
+
+fn text(token: &Token) -> String {
+	match token.kind {
+		TKind::Net => format!("{}.{{", token.text),
+		TKind::Key => format!("{};", token.text),
+		TKind::Typ => format!("{}:", token.text),
+		TKind::Comment => token.text.trim_end().to_string(),
+		_ => token.text.clone(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// kind and text of every token but whitespace
+	fn stream(source: &str) -> Vec<(TKind, String)> {
+		let (tokens, _) = tokenizer::trivia(source);
+		tokens
+			.iter()
+			.filter(|t| t.kind != TKind::Skip && t.kind != TKind::Newline)
+			.map(|t| (t.kind, text(t)))
+			.collect()
+	}
+
+	#[test]
+	fn round_trip() {
+		let source = "\n\n// leading comment\nlib.{   k; 2   // trailing\n\n\n\n  e.{ y;k }\n}\nadd: i32 i32 -> i32,   | x   y |  x y +\npair; [ 1  2 ]\n";
+		let formatted = format(source).unwrap();
+		assert_eq!(
+			formatted,
+			"// leading comment\nlib.{ k; 2 // trailing\n\n\te.{ y; k }\n}\nadd: i32 i32 -> i32, |x y| x y +\npair; [1 2]\n"
+		);
+		assert_eq!(format(&formatted).unwrap(), formatted);
+		assert_eq!(stream(&formatted), stream(source));
+	}
+}
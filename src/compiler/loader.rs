@@ -0,0 +1,226 @@
+use super::diagnostic::{self, Diagnostic, Sources};
+use super::parser::{self, EMap, Index, NMap, Network, TMap};
+use super::tokenizer;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// extension tried after the bare name when mapping a module name to a file
+const EXTENSION: &str = "z";
+
+struct Module {
+	root: String,
+	modified: Option<SystemTime>,
+	source: String,
+	nmap: NMap,
+	emap: EMap,
+	tmap: TMap,
+	diagnostics: Vec<Diagnostic>,
+	imports: Vec<Import>,
+}
+
+// module root and file of a clone source found in another file, and the
+// network that clones from it
+type Import = (String, PathBuf, Network);
+
+// Loads the files that clones like `library.windowing` refer to, keeping every
+// parsed module around so that watch mode only reparses what changed on disk.
+pub struct Loader {
+	search: Vec<PathBuf>,
+	// passed on to the parser for every module
+	shadowing: bool,
+	cache: HashMap<PathBuf, Module>,
+	// files each compiled source pulled in during its last compile
+	uses: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Loader {
+	pub fn new(search: Vec<PathBuf>, shadowing: bool) -> Loader {
+		Loader {
+			search,
+			shadowing,
+			cache: HashMap::new(),
+			uses: HashMap::new(),
+		}
+	}
+
+	// Merge every module the already parsed `source` imports, directly or not,
+	// into its maps. Each module lives under its own root key.
+	pub fn load(
+		&mut self,
+		source: &Path,
+		root: &str,
+		nmap: &mut NMap,
+		emap: &mut EMap,
+		tmap: &mut TMap,
+		sources: &mut Sources,
+	) -> Vec<Diagnostic> {
+		let mut diagnostics = Vec::new();
+		let mut loaded: Vec<PathBuf> = Vec::new();
+		let mut chain = vec![(root.to_string(), canonical(source))];
+
+		let imports = self.imports(nmap);
+		for (name, path, network) in imports {
+			self.visit(&name, &path, &network, &mut chain, &mut loaded, &mut diagnostics);
+		}
+
+		for path in &loaded {
+			let module = &self.cache[path];
+			// module indices point into their own maps
+			let moved: Vec<Option<Index>> = module
+				.nmap
+				.iter()
+				.map(|(key, value)| Some(nmap.push(key.clone(), value.clone())))
+				.collect();
+			parser::relink(nmap, &moved, moved.iter().flatten().copied());
+			for (key, value) in &module.emap {
+				emap.insert(key.clone(), value.clone());
+			}
+			for (key, value) in &module.tmap {
+				tmap.insert(key.clone(), value.clone());
+			}
+			sources.add(&module.root, path.to_string_lossy().as_ref(), &module.source);
+			for error in &module.diagnostics {
+				diagnostics.push(error.clone().in_module(&module.root));
+			}
+		}
+
+		self.uses.insert(canonical(source), loaded);
+		diagnostics
+	}
+
+	// compiled sources that have to be rebuilt when `path` changes
+	pub fn dependents(&self, path: &Path) -> Vec<PathBuf> {
+		let path = canonical(path);
+		let mut dependents: Vec<PathBuf> = Vec::new();
+		for (source, uses) in &self.uses {
+			if uses.contains(&path) {
+				dependents.push(source.clone());
+			}
+		}
+		dependents.sort();
+		dependents
+	}
+
+	fn visit(
+		&mut self,
+		root: &String,
+		path: &PathBuf,
+		network: &Network,
+		chain: &mut Vec<(String, PathBuf)>,
+		loaded: &mut Vec<PathBuf>,
+		diagnostics: &mut Vec<Diagnostic>,
+	) {
+		if let Some(start) = chain.iter().position(|(_, p)| p == path) {
+			let mut cycle: Vec<String> = chain[start..].iter().map(|(r, _)| r.clone()).collect();
+			cycle.push(root.clone());
+			let error = Diagnostic::error(
+				diagnostic::IMPORT_CYCLE,
+				format!("import cycle: {}", cycle.join(" -> ")),
+				network.meta,
+			)
+			.label(format!("{} is imported here", root))
+			.in_module(&network.module);
+			diagnostics.push(error);
+			return;
+		}
+		if loaded.contains(path) {
+			return;
+		}
+
+		self.parse(root, path);
+		loaded.push(path.clone());
+
+		chain.push((root.clone(), path.clone()));
+		let imports = self.cache[path].imports.clone();
+		for (name, import, network) in imports {
+			self.visit(&name, &import, &network, chain, loaded, diagnostics);
+		}
+		chain.pop();
+	}
+
+	// reparse a module unless the cached copy is still current
+	fn parse(&mut self, root: &String, path: &PathBuf) {
+		let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+		if let Some(module) = self.cache.get(path) {
+			if module.modified.is_some() && module.modified == modified && &module.root == root {
+				return;
+			}
+		}
+
+		let source = fs::read_to_string(path).unwrap_or_default();
+		let (tokens, mut diagnostics) = tokenizer::tokenizer(&source);
+		let (nmap, emap, tmap, mut errors) = parser::parser(root, tokens, self.shadowing);
+		diagnostics.append(&mut errors);
+
+		let imports = self.imports(&nmap);
+		let module = Module {
+			root: root.clone(),
+			modified,
+			source,
+			nmap,
+			emap,
+			tmap,
+			diagnostics,
+			imports,
+		};
+		self.cache.insert(path.clone(), module);
+	}
+
+	// clone sources that are not defined in the module but found on the search path
+	fn imports(&self, nmap: &NMap) -> Vec<Import> {
+		let mut imports: Vec<Import> = Vec::new();
+		let mut networks: Vec<&Network> = nmap.values().collect();
+		networks.sort_by(|a, b| a.path.cmp(&b.path));
+
+		for network in networks {
+			for copy in &network.copy {
+				if defined(nmap, network, copy) {
+					continue;
+				}
+				if let Some((name, path)) = self.find(copy) {
+					if !imports.iter().any(|(_, p, _)| p == &path) {
+						imports.push((name, path, network.clone()));
+					}
+				}
+			}
+		}
+		imports
+	}
+
+	// `a.b.c` is looked up as the files a/b/c, a/b and a, longest first
+	fn find(&self, name: &str) -> Option<(String, PathBuf)> {
+		let segments: Vec<&str> = name.split('.').collect();
+		for length in (1..=segments.len()).rev() {
+			let relative: PathBuf = segments[..length].iter().collect();
+			for dir in &self.search {
+				let bare = dir.join(&relative);
+				let extended = bare.with_extension(EXTENSION);
+				for candidate in [bare, extended] {
+					if candidate.is_file() {
+						return Some((segments[..length].join("."), canonical(&candidate)));
+					}
+				}
+			}
+		}
+		None
+	}
+}
+
+// whether the head of a clone source names a key visible from the network
+fn defined(nmap: &NMap, network: &Network, copy: &str) -> bool {
+	let segments: Vec<&str> = copy.split('.').collect();
+	if network.path.split('.').next() == Some(segments[0]) {
+		return true;
+	}
+	(1..=segments.len()).any(|length| {
+		parser::name(&segments[..length].join("."))
+			.and_then(|name| parser::find(nmap, network.parent, &name))
+			.is_some()
+	})
+}
+
+fn canonical(path: &Path) -> PathBuf {
+	fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
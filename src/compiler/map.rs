@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// A string keyed map that remembers the order keys were first inserted in, so
+// that stage dumps come out the same on every run.
+#[derive(Clone, Default)]
+pub struct Map<V> {
+	entries: Vec<(String, V)>,
+	index: HashMap<String, usize>,
+}
+
+impl<V> Map<V> {
+	pub fn new() -> Map<V> {
+		Map {
+			entries: Vec::new(),
+			index: HashMap::new(),
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn contains_key(&self, key: &str) -> bool {
+		self.index.contains_key(key)
+	}
+
+	// position of the key, stable until an entry before it is removed
+	pub fn index(&self, key: &str) -> Option<usize> {
+		self.index.get(key).copied()
+	}
+
+	pub fn key(&self, index: usize) -> &String {
+		&self.entries[index].0
+	}
+
+	pub fn at(&self, index: usize) -> &V {
+		&self.entries[index].1
+	}
+
+	pub fn at_mut(&mut self, index: usize) -> &mut V {
+		&mut self.entries[index].1
+	}
+
+	pub fn get(&self, key: &str) -> Option<&V> {
+		self.index.get(key).map(|i| &self.entries[*i].1)
+	}
+
+	pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+		match self.index.get(key) {
+			Some(i) => Some(&mut self.entries[*i].1),
+			None => None,
+		}
+	}
+
+	// a key that is already present keeps its place
+	pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+		match self.index.get(&key) {
+			Some(i) => Some(std::mem::replace(&mut self.entries[*i].1, value)),
+			None => {
+				self.push(key, value);
+				None
+			}
+		}
+	}
+
+	// insert and return the position of the entry
+	pub fn push(&mut self, key: String, value: V) -> usize {
+		if let Some(i) = self.index.get(&key) {
+			self.entries[*i].1 = value;
+			return *i;
+		}
+		let i = self.entries.len();
+		self.index.insert(key.clone(), i);
+		self.entries.push((key, value));
+		i
+	}
+
+	pub fn remove(&mut self, key: &str) -> Option<V> {
+		let i = self.index.remove(key)?;
+		let (_, value) = self.entries.remove(i);
+		self.reindex(i);
+		Some(value)
+	}
+
+	// returns where each entry moved to, None for the ones dropped
+	pub fn retain<F: FnMut(&String, &V) -> bool>(&mut self, mut keep: F) -> Vec<Option<usize>> {
+		let mut moved = Vec::with_capacity(self.entries.len());
+		let mut next = 0;
+		for (k, v) in &self.entries {
+			if keep(k, v) {
+				moved.push(Some(next));
+				next += 1;
+			} else {
+				moved.push(None);
+			}
+		}
+		if next != self.entries.len() {
+			let mut i = 0;
+			self.entries.retain(|_| {
+				i += 1;
+				moved[i - 1].is_some()
+			});
+			self.index.clear();
+			self.reindex(0);
+		}
+		moved
+	}
+
+	pub fn keys(&self) -> impl Iterator<Item = &String> {
+		self.entries.iter().map(|(k, _)| k)
+	}
+
+	pub fn values(&self) -> impl Iterator<Item = &V> {
+		self.entries.iter().map(|(_, v)| v)
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+		self.entries.iter().map(|(k, v)| (k, v))
+	}
+
+	fn reindex(&mut self, from: usize) {
+		for (i, (k, _)) in self.entries.iter().enumerate().skip(from) {
+			self.index.insert(k.clone(), i);
+		}
+	}
+}
+
+impl<V: Clone> Map<V> {
+	// the same entries ordered by key
+	pub fn sorted(&self) -> Map<V> {
+		let mut entries = self.entries.clone();
+		entries.sort_by(|a, b| a.0.cmp(&b.0));
+		let mut map = Map::new();
+		for (k, v) in entries {
+			map.insert(k, v);
+		}
+		map
+	}
+}
+
+impl<'a, V> IntoIterator for &'a Map<V> {
+	type Item = (&'a String, &'a V);
+	type IntoIter = std::iter::Map<std::slice::Iter<'a, (String, V)>, fn(&'a (String, V)) -> (&'a String, &'a V)>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.entries.iter().map(|(k, v)| (k, v))
+	}
+}
+
+impl<V: fmt::Debug> fmt::Debug for Map<V> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
@@ -1,7 +1,14 @@
 // pub mod expander;
+pub mod builtin;
 pub mod clone;
-// pub mod deref;
+pub mod deref;
+pub mod diagnostic;
+pub mod dot;
+pub mod export;
+pub mod format;
+pub mod loader;
+pub mod map;
 pub mod parser;
 // pub mod reducer;
 pub mod tokenizer;
-// pub mod typer;
+pub mod typer;
@@ -1,64 +1,338 @@
-use super::tokenizer::{Kind as TKind, Token};
+// every parse step returns its syntax error by value, recovery records it
+#![allow(clippy::result_large_err)]
+
+use super::diagnostic::{self, Diagnostic};
+use super::map::Map;
+use super::tokenizer::{Kind as TKind, Meta, Span, Token};
+use super::typer::{self, Format, Type};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::num::IntErrorKind;
+use std::rc::Rc;
 
 pub type Index = usize;
 
-pub type EMap = HashMap<String, Element>;
-pub type TMap = HashMap<String, Element>;
-pub type NMap = HashMap<String, Network>;
+// key names are interned, every network with the same name shares its text
+// and names compare by pointer
+pub type Name = Rc<str>;
+
+thread_local! {
+	// one set for the file and every module it loads
+	static NAMES: RefCell<HashSet<Name>> = RefCell::new(HashSet::new());
+}
+
+pub fn intern(text: &str) -> Name {
+	NAMES.with(|names| {
+		let mut names = names.borrow_mut();
+		match names.get(text) {
+			Some(name) => name.clone(),
+			None => {
+				let name: Name = Rc::from(text);
+				names.insert(name.clone());
+				name
+			}
+		}
+	})
+}
+
+// the interned name, None when no key was ever called that
+pub fn name(text: &str) -> Option<Name> {
+	NAMES.with(|names| names.borrow().get(text).cloned())
+}
+
+// keyed by keychain path, in the order the definitions appear in the source.
+// The nmap is also the arena networks link to each other through by Index.
+pub type EMap = Map<Element>;
+pub type TMap = Map<Element>;
+pub type NMap = Map<Network>;
 
 #[derive(Debug, Clone)]
 pub struct Element {
-	// meta: Meta,
-	kind: Kind,
-	para: usize,
-	text: String,
-	head: Vec<Element>,
-	body: Vec<Element>,
+	pub meta: Span,
+	pub kind: Kind,
+	pub para: usize,
+	pub text: String,
+	// absolute keychain path a term refers to, filled in by the deref pass
+	pub path: String,
+	// the value of a literal, parsed once by the parser
+	pub literal: Option<Literal>,
+	// what the element leaves on the stack, filled in by the typer
+	pub typ: Type,
+	pub head: Vec<Element>,
+	pub body: Vec<Element>,
 }
 
 impl Element {
 	fn new(kind: Kind) -> Element {
 		Element {
-			// meta: Meta { col: 0, row: 0 },
+			meta: Span::default(),
 			kind,
 			para: 0,
 			text: String::new(),
+			path: String::new(),
+			literal: None,
+			typ: Type::Unknown,
 			head: Vec::new(),
 			body: Vec::new(),
 		}
 	}
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Integer(u64),
+	Decimal(f64),
+	// strings are byte arrays
+	Bytes(Vec<u8>),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Literal {
+	pub value: Value,
+	// the base type a suffix gives the literal, as in `10i32`
+	pub width: Option<Type>,
+}
+
+// a literal dumps as source, `10i32` or `"a\xFF"`
+impl fmt::Debug for Literal {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match &self.value {
+			Value::Integer(value) => write!(f, "{}", value)?,
+			Value::Decimal(value) => write!(f, "{:?}", value)?,
+			Value::Bytes(bytes) => write!(f, "\"{}\"", escape(bytes))?,
+		}
+		match &self.width {
+			Some(width) => write!(f, "{}", width),
+			None => Ok(()),
+		}
+	}
+}
+
+// the bytes as string source, `\xFF` for the bytes that are not UTF-8
+fn escape(bytes: &[u8]) -> String {
+	let mut text = String::new();
+	for chunk in bytes.utf8_chunks() {
+		for c in chunk.valid().chars() {
+			match c {
+				'"' => text.push_str("\\\""),
+				'\\' => text.push_str("\\\\"),
+				'\n' => text.push_str("\\n"),
+				'\t' => text.push_str("\\t"),
+				'\r' => text.push_str("\\r"),
+				'\0' => text.push_str("\\0"),
+				c if c.is_control() => text.push_str(&format!("\\u{{{:x}}}", c as u32)),
+				c => text.push(c),
+			}
+		}
+		for byte in chunk.invalid() {
+			text.push_str(&format!("\\x{:02X}", byte));
+		}
+	}
+	text
+}
+
+// `10`, `1_000`, `0xFF`, `0b1010`, `0.5`, `1e-3` and any of them with a
+// suffix naming its base type, as in `10i32` or `0.9f32`
+pub fn literal(text: &str) -> Result<Literal, String> {
+	let hex = text.starts_with("0x");
+	let binary = text.starts_with("0b");
+	// f and c are hex digits
+	let letters: &[char] = if hex { &['i', 'u'] } else { &['i', 'u', 'f', 'c'] };
+	let (digits, width) = match text.rfind(letters) {
+		Some(i) => match typer::base(&text[i..]) {
+			Some(width @ Type::Base(..)) => (&text[..i], Some(width)),
+			_ => return Err(format!("unknown suffix {}", &text[i..])),
+		},
+		None => (text, None),
+	};
+
+	let clean = digits.replace('_', "");
+	let integer = |parsed: Result<u64, std::num::ParseIntError>| match parsed {
+		Ok(value) => Ok(Value::Integer(value)),
+		Err(e) if *e.kind() == IntErrorKind::PosOverflow => Err(format!("{} does not fit in 64 bits", digits)),
+		Err(_) => Err(format!("invalid number {}", text)),
+	};
+	let value = if hex {
+		if !hex_digits(&clean[2..]) {
+			return Err(format!("invalid number {}", text));
+		}
+		integer(u64::from_str_radix(&clean[2..], 16))?
+	} else if binary {
+		if !clean[2..].chars().all(|c| c == '0' || c == '1') {
+			return Err(format!("invalid number {}", text));
+		}
+		integer(u64::from_str_radix(&clean[2..], 2))?
+	} else if clean.contains(['.', 'e', 'E']) {
+		match clean.parse::<f64>() {
+			Ok(value) if value.is_finite() => Value::Decimal(value),
+			Ok(_) => return Err(format!("{} does not fit in 64 bits", digits)),
+			Err(_) => return Err(format!("invalid number {}", text)),
+		}
+	} else {
+		integer(clean.parse())?
+	};
+
+	if let (Value::Decimal(_), Some(Type::Base(Format::Signed | Format::Unsigned, _))) = (&value, &width) {
+		return Err(format!("a decimal cannot be {}", width.unwrap()));
+	}
+	Ok(Literal { value, width })
+}
+
+// the UTF-8 bytes of a quoted string, with the escapes `\n \t \r \0 \\ \"`,
+// `\xFF` for a byte and `\u{2192}` for a character
+pub fn string(text: &str) -> Result<Vec<u8>, String> {
+	let inner = &text[1..text.len() - 1];
+	let mut bytes = Vec::new();
+	let mut chars = inner.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			let mut buffer = [0; 4];
+			bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+			continue;
+		}
+		match chars.next() {
+			Some('n') => bytes.push(b'\n'),
+			Some('t') => bytes.push(b'\t'),
+			Some('r') => bytes.push(b'\r'),
+			Some('0') => bytes.push(0),
+			Some('\\') => bytes.push(b'\\'),
+			Some('"') => bytes.push(b'"'),
+			Some('x') => {
+				let digits: String = chars.by_ref().take(2).collect();
+				match u8::from_str_radix(&digits, 16) {
+					Ok(byte) if digits.len() == 2 && hex_digits(&digits) => bytes.push(byte),
+					_ => return Err(format!("\\x{} is not a byte, expected two hex digits", digits)),
+				}
+			}
+			Some('u') => {
+				let rest = chars.as_str();
+				let code = match (rest.strip_prefix('{'), rest.find('}')) {
+					(Some(_), Some(end)) => &rest[1..end],
+					_ => return Err("expected \\u{...} with a hex character code".to_string()),
+				};
+				let c = match u32::from_str_radix(code, 16).ok().and_then(char::from_u32) {
+					Some(c) if hex_digits(code) => c,
+					_ => return Err(format!("\\u{{{}}} is not a character", code)),
+				};
+				let mut buffer = [0; 4];
+				bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+				chars = rest[code.len() + 2..].chars();
+			}
+			Some(other) => return Err(format!("unknown escape \\{}", other)),
+			None => return Err("a string cannot end with \\".to_string()),
+		}
+	}
+	Ok(bytes)
+}
+
+// from_str_radix also takes a sign
+fn hex_digits(digits: &str) -> bool {
+	!digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit())
+}
+
 #[derive(Debug, Clone)]
 pub struct Network {
-	// meta: Meta,
+	pub meta: Span,
 	pub node: Node,
 	pub copy: Vec<String>,
 	pub path: String,
-	pub keys: Vec<String>,
+	// keychain path the spans point into, a copy keeps the one of its original
+	pub module: String,
+	pub name: Name,
+	pub parent: Option<Index>,
+	pub keys: Vec<Index>,
 }
 
 impl Network {
 	fn new(node: Node) -> Network {
 		Network {
-			// meta: Meta { col: 0, row: 0 },
+			meta: Span::default(),
 			node,
 			copy: Vec::new(),
 			path: String::new(),
+			module: String::new(),
+			name: Rc::from(""),
+			parent: None,
 			keys: Vec::new(),
 		}
 	}
 }
 
+// follow the networks to where they moved, `moved` is indexed by old position
+// and has None for the networks that are gone
+pub fn relink(nmap: &mut NMap, moved: &[Option<Index>], networks: impl IntoIterator<Item = Index>) {
+	for i in networks {
+		let network = nmap.at_mut(i);
+		network.parent = network.parent.and_then(|p| moved[p]);
+		network.keys = network.keys.iter().filter_map(|k| moved[*k]).collect();
+	}
+}
+
+// the same networks ordered by key
+pub fn sorted(nmap: &NMap) -> NMap {
+	let mut sorted = nmap.sorted();
+	let moved: Vec<Option<Index>> = nmap.keys().map(|k| sorted.index(k)).collect();
+	relink(&mut sorted, &moved, 0..nmap.len());
+	for i in 0..sorted.len() {
+		sorted.at_mut(i).keys.sort_unstable();
+	}
+	sorted
+}
+
+// where the spans of a network were written, which differs from its key for copies
+pub fn module<'a>(nmap: &'a NMap, key: &'a str) -> &'a str {
+	nmap.get(key).map_or(key, |network| &network.module)
+}
+
+// the key of a graph with the given name
+pub fn child(nmap: &NMap, graph: Index, name: &Name) -> Option<Index> {
+	nmap.at(graph)
+		.keys
+		.iter()
+		.copied()
+		.find(|k| Rc::ptr_eq(&nmap.at(*k).name, name))
+}
+
+// a possibly dotted name from the scope outwards, ending with the module roots.
+// A dotted key is tried whole before it is split into the keys of graphs.
+pub fn lookup(nmap: &NMap, scope: Option<Index>, text: &str) -> Option<Index> {
+	let segments: Vec<&str> = text.split('.').collect();
+	for length in (1..=segments.len()).rev() {
+		let head = match name(&segments[..length].join(".")) {
+			Some(head) => head,
+			None => continue,
+		};
+		if let Some(mut index) = find(nmap, scope, &head) {
+			for segment in &segments[length..] {
+				index = child(nmap, index, &name(segment)?)?;
+			}
+			return Some(index);
+		}
+	}
+	nmap.index(text)
+}
+
+// the nearest key with the given name, from the scope outwards
+pub fn find(nmap: &NMap, scope: Option<Index>, name: &Name) -> Option<Index> {
+	let mut graph = scope;
+	while let Some(g) = graph {
+		if let Some(k) = child(nmap, g, name) {
+			return Some(k);
+		}
+		graph = nmap.at(g).parent;
+	}
+	None
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Node {
 	// Nothing,
 	//
 	Graph,
-	Clone,
 	Point,
+	// a graph written as `source { overrides }`, a graph once the clone pass expanded it
+	Clone,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -70,92 +344,224 @@ pub enum Kind {
 	//
 	Para,
 	Term,
-	// Pun,
+	Pun,
 	Type,
 	//
 	// Number,
 	Integer,
 	Decimal,
-	// String,
+	String,
 	//
 	Function,
 	// Operator,
+	Error,
 }
 
 struct State {
 	cursor: RefCell<usize>,
 	tokens: Vec<Token>,
 	keychain: Vec<String>,
+	// the networks being parsed, innermost last
+	scopes: Vec<Index>,
 	nmap: NMap,
 	emap: EMap,
 	tmap: TMap,
+	// a later definition of a key replaces the earlier one instead of being an error
+	shadowing: bool,
+	diagnostics: Vec<Diagnostic>,
 }
 
+// tokens at which the parser resumes after a syntax error
+const SYNC: [TKind; 5] = [TKind::BracketRT, TKind::Key, TKind::Typ, TKind::Net, TKind::Dot];
+
 pub fn parser(
-	filename: &String,
+	filename: &str,
 	tokens: Vec<Token>,
-) -> Result<(NMap, EMap, TMap), String> {
+	shadowing: bool,
+) -> (NMap, EMap, TMap, Vec<Diagnostic>) {
 	let mut state = State {
 		cursor: RefCell::new(0),
 		tokens,
 		keychain: Vec::new(),
-		nmap: HashMap::new(),
-		emap: HashMap::new(),
-		tmap: HashMap::new(),
+		scopes: Vec::new(),
+		nmap: Map::new(),
+		emap: Map::new(),
+		tmap: Map::new(),
+		shadowing,
+		diagnostics: Vec::new(),
 	};
 
-	state.program(filename)?;
-	Ok((state.nmap, state.emap, state.tmap))
-	// Ok(state.Networks)
-	// Ok()
+	state.program(filename);
+	(state.nmap, state.emap, state.tmap, state.diagnostics)
 }
 
 impl State {
-	fn program(&mut self, filename: &String) -> Result<Network, String> {
-		self.keychain.push(filename.clone());
-		let mut program = self.networks(&[])?;
+	fn program(&mut self, filename: &str) -> Network {
+		self.keychain.push(filename.to_string());
+		self.reserve();
+		let start = self.start();
+		let mut program = self.networks(&[]);
+		program.meta = Span::new(start, self.end());
 		program.path = self.keychain.join(".");
-		self.nmap.insert(program.path.clone(), program.clone());
+		program.module = program.path.clone();
+		program.name = intern(filename);
+		self.place(&mut program);
 		self.keychain.pop();
-		Ok(program)
+		program
 	}
 
-	fn networks(&mut self, stop: &[TKind]) -> Result<Network, String> {
+	fn networks(&mut self, stop: &[TKind]) -> Network {
 		let mut graph = Network::new(Node::Graph);
 
-		graph.copy = self.clones(stop)?;
+		graph.copy = self.clones(stop);
 		while self.until(0, stop) {
-			graph.keys.push(self.network()?.path.clone());
+			if self.any(0, &[TKind::Net, TKind::Typ, TKind::Key, TKind::Dot]) {
+				self.network();
+			} else {
+				let token = self.get(0).unwrap();
+				let error = Diagnostic::error(
+					diagnostic::UNEXPECTED_TOKEN,
+					format!("unexpected token {:?} of {:?}", token.text, token.kind),
+					token.span(),
+				)
+				.label("expected a key, a typed key, a pun or a graph".to_string());
+				self.diagnostics.push(error);
+				*self.cursor.borrow_mut() += 1;
+				self.synchronize();
+			}
 		}
-		graph.keys.sort();
-		graph.keys.dedup();
-
-		Ok(graph)
+		graph
 	}
 
-	fn network(&mut self) -> Result<Network, String> {
+	fn network(&mut self) -> Network {
 		let kind = self.get(0).unwrap().kind;
-		let text = self.eat(kind)?.text.clone();
+		let start = self.start();
+		let token = self.get(0).unwrap().span();
+		let text = self.get(0).unwrap().text.clone();
+		*self.cursor.borrow_mut() += 1;
 		//
-		self.keychain.push(text);
-		let mut network = match kind {
-			TKind::Net => self.graph()?,
-			TKind::Typ => self.typed()?,
-			TKind::Key => self.point()?,
+		let key = match kind {
+			// `.a.b` is keyed by its last segment
+			TKind::Dot => text.rsplit('.').next().unwrap().to_string(),
+			_ => text.clone(),
+		};
+		let name = intern(&key);
+		self.keychain.push(key);
+		self.redefine(token);
+		self.reserve();
+		let result = match kind {
+			TKind::Net => self.graph(),
+			TKind::Typ => self.typed(),
+			TKind::Key => self.point(),
+			TKind::Dot => self.pun(&text[1..]),
 			_ => {
 				panic!("Should never hit")
 			}
 		};
+		let mut network = match result {
+			Ok(network) => network,
+			Err(error) => self.recover(error),
+		};
 
+		network.meta = Span::new(start, self.end());
 		network.path = self.keychain.join(".");
-		self.nmap.insert(network.path.clone(), network.clone());
+		network.module = network.path.clone();
+		network.name = name;
+		self.place(&mut network);
 		self.keychain.pop();
 
-		Ok(network)
+		network
 	}
 
-	fn typed(&mut self) -> Result<Network, String> {
+	// drop an earlier definition of the key about to be parsed, along with
+	// everything below it, so only the later one is left
+	fn redefine(&mut self, token: Span) {
+		let path = self.keychain.join(".");
+		let previous = match self.nmap.get(&path) {
+			Some(previous) => previous.meta,
+			None => return,
+		};
+		if !self.shadowing {
+			let key = self.keychain.last().unwrap();
+			let error = Diagnostic::error(
+				diagnostic::DUPLICATE_KEY,
+				format!("{} is defined more than once", key),
+				token,
+			)
+			.label("defined again here".to_string())
+			.secondary(previous, format!("{} is first defined here", key))
+			.note("run with --shadowing to let later definitions replace earlier ones".to_string());
+			self.diagnostics.push(error);
+		}
+
+		let mut stale: HashSet<String> = HashSet::new();
+		let mut below = vec![self.nmap.index(&path).unwrap()];
+		while let Some(i) = below.pop() {
+			stale.insert(self.nmap.key(i).clone());
+			below.extend(&self.nmap.at(i).keys);
+		}
+		let moved = self.nmap.retain(|k, _| !stale.contains(k));
+		self.emap.retain(|k, _| !stale.contains(k));
+		self.tmap.retain(|k, _| !stale.contains(k));
+		let count = self.nmap.len();
+		relink(&mut self.nmap, &moved, 0..count);
+		for scope in self.scopes.iter_mut() {
+			*scope = moved[*scope].unwrap();
+		}
+	}
+
+	// a graph takes its place in the nmap before the networks inside it, as a
+	// key of the graph it is in
+	fn reserve(&mut self) {
+		let parent = self.scopes.last().copied();
+		let mut network = Network::new(Node::Point);
+		network.parent = parent;
+		let index = self.nmap.push(self.keychain.join("."), network);
+		if let Some(parent) = parent {
+			self.nmap.at_mut(parent).keys.push(index);
+		}
+		self.scopes.push(index);
+	}
+
+	// the parsed network replaces its reservation and keeps its links
+	fn place(&mut self, network: &mut Network) {
+		let index = self.scopes.pop().unwrap();
+		let reserved = self.nmap.at_mut(index);
+		network.parent = reserved.parent;
+		network.keys = std::mem::take(&mut reserved.keys);
+		*reserved = network.clone();
+	}
+
+	// record the error, skip to the next definition and leave an error node in its place
+	fn recover(&mut self, error: Diagnostic) -> Network {
+		let mut element = Element::new(Kind::Error);
+		element.meta = error.primary.span;
+		element.text = error.message.clone();
+		self.diagnostics.push(error);
+		self.synchronize();
+
+		self.emap.insert(self.keychain.join("."), element);
+		Network::new(Node::Point)
+	}
+
+	fn synchronize(&mut self) {
+		while self.until(0, &SYNC) {
+			*self.cursor.borrow_mut() += 1;
+		}
+	}
+
+	fn typed(&mut self) -> Result<Network, Diagnostic> {
+		// `name: source { overrides }` is an untyped clone
+		if self.is(0, TKind::Ref) && self.is(1, TKind::BracketLF) {
+			return self.clone();
+		}
+		// `name: { keys }` is a graph like `name.{ keys }`
+		if self.is(0, TKind::BracketLF) {
+			self.eat(TKind::BracketLF)?;
+			return self.graph();
+		}
 		let mut typ = Element::new(Kind::Type);
+		let start = self.start();
 
 		typ.body = self.elements(
 			&Vec::new(),
@@ -163,6 +569,7 @@ impl State {
 				TKind::BracketRT,
 				TKind::Key,
 				TKind::Typ,
+				TKind::Net,
 				TKind::Com,
 				TKind::Arrow,
 			],
@@ -177,65 +584,107 @@ impl State {
 					TKind::BracketRT,
 					TKind::Key,
 					TKind::Typ,
+					TKind::Net,
 					TKind::Com,
 					TKind::Arrow,
 				],
 			)?;
 		}
 
+		typ.meta = self.since(start);
 		self.tmap.insert(self.keychain.join("."), typ);
 
 		if self.is(0, TKind::Com) {
 			self.eat(TKind::Com)?;
 		}
-		self.point()?;
-
-		Ok(Network::new(Node::Point))
+		// `name: f32, { keys }` is a graph whose points take its type
+		if self.is(0, TKind::BracketLF) {
+			self.eat(TKind::BracketLF)?;
+			return self.graph();
+		}
+		self.point()
 	}
 
-	fn point(&mut self) -> Result<Network, String> {
+	fn point(&mut self) -> Result<Network, Diagnostic> {
+		if self.is(0, TKind::Ref) && self.is(1, TKind::BracketLF) {
+			return self.clone();
+		}
 		let network = Network::new(Node::Point);
 		let keystring = self.keychain.join(".");
 
-		let stack = self.stack(&Vec::new(), &[TKind::BracketRT, TKind::Key, TKind::Typ])?;
+		let stack = self.stack(&Vec::new(), &SYNC)?;
 		self.emap.insert(keystring, stack);
 
 		Ok(network)
 	}
 
-	fn graph(&mut self) -> Result<Network, String> {
+	// `.name` defines name as whatever name means outside of the graph
+	fn pun(&mut self, text: &str) -> Result<Network, Diagnostic> {
+		let token = &self.tokens[*self.cursor.borrow() - 1];
+		let mut pun = Element::new(Kind::Pun);
+		pun.meta = token.span();
+		pun.text = text.to_string();
+
+		let mut stack = Element::new(Kind::Tuple);
+		stack.meta = pun.meta;
+		stack.body.push(pun);
+		self.emap.insert(self.keychain.join("."), stack);
+
+		Ok(Network::new(Node::Point))
+	}
+
+	fn graph(&mut self) -> Result<Network, Diagnostic> {
 		// self.eat(TKind::BracketLF)?;
-		let networks = self.networks(&[TKind::BracketRT])?;
-		self.eat(TKind::BracketRT)?;
+		let opener = self.tokens[*self.cursor.borrow() - 1].span();
+		let networks = self.networks(&[TKind::BracketRT]);
+		if let Err(error) = self.eat(TKind::BracketRT) {
+			// keep the graph, its keys were parsed fine
+			let error = error.secondary(opener, "graph opened here".to_string());
+			self.diagnostics.push(error);
+		}
 		Ok(networks)
 	}
 
-	fn clones(&mut self, stop: &[TKind]) -> Result<Vec<String>, String> {
+	fn clones(&mut self, stop: &[TKind]) -> Vec<String> {
 		let mut clones = Vec::new();
 
-		while self.until(0, &[stop, &[TKind::Typ, TKind::Key, TKind::Net]].concat()) {
-			clones.push(self.eat(TKind::Ref)?.text.clone());
+		while self.until(0, &[stop, &[TKind::Typ, TKind::Key, TKind::Net, TKind::Dot]].concat()) {
+			match self.eat(TKind::Ref) {
+				Ok(token) => clones.push(token.text.clone()),
+				Err(error) => {
+					let error = error.label("expected a graph to clone".to_string());
+					self.diagnostics.push(error);
+					*self.cursor.borrow_mut() += 1;
+				}
+			}
 			if self.is(0, TKind::Com) {
-				self.eat(TKind::Com)?;
+				*self.cursor.borrow_mut() += 1;
 			}
 		}
 
-		Ok(clones)
+		clones
 	}
 
-	// fn clone(&mut self) -> Result<Network, String> {
-	// 	let text = self.eat(TKind::Ref)?.text.clone();
-	// 	self.eat(TKind::BracketLF)?;
-	// 	let mut networks = self.networks(&[TKind::BracketRT])?;
-	// 	self.eat(TKind::BracketRT)?;
-	// 	networks.node = Node::Clone;
-	// 	networks.text = text;
-	// 	Ok(networks)
-	// }
+	// the source goes first in the copy list, the networks inside the brackets override its keys
+	fn clone(&mut self) -> Result<Network, Diagnostic> {
+		let text = self.eat(TKind::Ref)?.text.clone();
+		let opener = self.get(0).unwrap().span();
+		self.eat(TKind::BracketLF)?;
+		let mut networks = self.networks(&[TKind::BracketRT]);
+		if let Err(error) = self.eat(TKind::BracketRT) {
+			let error = error.secondary(opener, "clone opened here".to_string());
+			self.diagnostics.push(error);
+		}
+		networks.node = Node::Clone;
+		networks.copy.insert(0, text);
+		Ok(networks)
+	}
 
-	fn stack(&mut self, pars: &Vec<String>, stop: &[TKind]) -> Result<Element, String> {
+	fn stack(&mut self, pars: &Vec<String>, stop: &[TKind]) -> Result<Element, Diagnostic> {
 		let mut element = Element::new(Kind::Tuple);
+		let start = self.start();
 		element.body = self.elements(pars, stop)?;
+		element.meta = self.since(start);
 
 		Ok(element)
 	}
@@ -243,17 +692,33 @@ impl State {
 		&mut self,
 		pars: &Vec<String>,
 		stop: &[TKind],
-	) -> Result<Vec<Element>, String> {
+	) -> Result<Vec<Element>, Diagnostic> {
 		let mut elements = Vec::new();
 
-		while self.until(0, stop) {
-			elements.push(self.element(pars)?);
+		// an unclosed bracket ends at the next definition
+		while self.until(0, stop) && self.until(0, &SYNC) {
+			let cursor = *self.cursor.borrow();
+			match self.element(pars) {
+				Ok(element) => elements.push(element),
+				// definitions and closing brackets are left to network level recovery
+				Err(error) if !self.until(0, &SYNC) => return Err(error),
+				Err(error) => {
+					let mut element = Element::new(Kind::Error);
+					element.meta = error.primary.span;
+					element.text = error.message.clone();
+					elements.push(element);
+					self.diagnostics.push(error);
+					if *self.cursor.borrow() == cursor {
+						*self.cursor.borrow_mut() += 1;
+					}
+				}
+			}
 		}
 
 		Ok(elements)
 	}
 
-	fn element(&mut self, pars: &Vec<String>) -> Result<Element, String> {
+	fn element(&mut self, pars: &Vec<String>) -> Result<Element, Diagnostic> {
 		let token = self.get(0).unwrap();
 		match token.kind {
 			TKind::ParenLF => self.tuple(pars),
@@ -263,29 +728,34 @@ impl State {
 			// TKind::Number => self.number(),
 			TKind::Integer => self.integer(),
 			TKind::Decimal => self.decimal(),
-			// TKind::String => self.string(),
-			_ => {
-				return Err(format!(
-					"While processing word stack, unexpected token: {:?} was encountered",
-					token
-				))
-			}
+			TKind::String => self.string(),
+			_ => Err(Diagnostic::error(
+				diagnostic::UNEXPECTED_TOKEN,
+				format!(
+					"unexpected token {:?} of {:?} while processing word stack",
+					token.text, token.kind
+				),
+				token.span(),
+			)
+			.label("not allowed in a stack".to_string())),
 		}
 	}
 
-	fn function(&mut self, pars: &Vec<String>) -> Result<Element, String> {
+	fn function(&mut self, pars: &[String]) -> Result<Element, Diagnostic> {
+		let start = self.start();
 		let head = self.pars(&mut pars.len())?;
-		let mut pars: Vec<String> = pars.clone();
+		let mut pars: Vec<String> = pars.to_vec();
 		for par in &head {
 			pars.push(par.text.clone());
 		}
 		let mut func = self.stack(
-			&mut pars,
+			&pars,
 			&[
 				TKind::BracketRT,
 				TKind::ParenRT,
 				TKind::Key,
 				TKind::Typ,
+				TKind::Net,
 				TKind::Com,
 				TKind::Arrow,
 				TKind::SquarenRT,
@@ -293,45 +763,57 @@ impl State {
 		)?;
 		func.head = head;
 		func.kind = Kind::Function;
+		func.meta = Span::new(start, self.end());
 		Ok(func)
 	}
 
-	fn pars(&mut self, count: &mut usize) -> Result<Vec<Element>, String> {
+	fn pars(&mut self, count: &mut usize) -> Result<Vec<Element>, Diagnostic> {
 		let mut pars: Vec<Element> = Vec::new();
 		self.eat(TKind::Post)?;
-		while self.until(0, &[TKind::Post]) {
-			pars.push(self.par(*count)?);
-			*count += 1;
+		while self.until(0, &[TKind::Post]) && self.until(0, &SYNC) {
+			match self.par(*count) {
+				Ok(par) => {
+					pars.push(par);
+					*count += 1;
+				}
+				Err(error) => {
+					self.diagnostics.push(error.label("expected a parameter name".to_string()));
+					*self.cursor.borrow_mut() += 1;
+				}
+			}
 		}
 		self.eat(TKind::Post)?;
 		Ok(pars)
 	}
 
-	fn par(&mut self, index: Index) -> Result<Element, String> {
+	fn par(&mut self, index: Index) -> Result<Element, Diagnostic> {
 		let token = self.eat(TKind::Ref)?;
 		let mut element = Element::new(Kind::Para);
+		element.meta = token.span();
 		element.para = index;
 		element.text = token.text.clone();
 		Ok(element)
 	}
 
-	fn term(&mut self, pars: &Vec<String>) -> Result<Element, String> {
+	fn term(&mut self, pars: &[String]) -> Result<Element, Diagnostic> {
 		let ref_token = self.eat(TKind::Ref)?;
+		let meta = ref_token.span();
 		let text = ref_token.text.clone();
 		let mut includes = false;
-		let mut smarap = pars.clone();
+		let mut smarap = pars.to_vec();
 		smarap.reverse();
 		//
 		let mut index = 0;
 		for (i, para) in smarap.iter().enumerate() {
 			if para == &text {
 				includes = true;
-				index = (i as i64 - pars.len() as i64 + 1).abs() as usize;
+				index = (i as i64 - pars.len() as i64 + 1).unsigned_abs() as usize;
 				break;
 			}
 		}
 
 		let mut element = Element::new(Kind::Nothing);
+		element.meta = meta;
 		element.text = text;
 		if includes {
 			element.kind = Kind::Para;
@@ -342,51 +824,118 @@ impl State {
 		Ok(element)
 	}
 
-	fn tuple(&mut self, pars: &Vec<String>) -> Result<Element, String> {
+	fn tuple(&mut self, pars: &Vec<String>) -> Result<Element, Diagnostic> {
+		let start = self.start();
 		self.eat(TKind::ParenLF)?;
-		let tuple = self.stack(pars, &[TKind::ParenRT])?;
+		let mut tuple = self.stack(pars, &[TKind::ParenRT])?;
 		self.eat(TKind::ParenRT)?;
+		tuple.meta = Span::new(start, self.end());
 		Ok(tuple)
 	}
 
-	fn array(&mut self, pars: &Vec<String>) -> Result<Element, String> {
+	fn array(&mut self, pars: &Vec<String>) -> Result<Element, Diagnostic> {
+		let start = self.start();
 		self.eat(TKind::SquarenLF)?;
 		let mut array = self.stack(pars, &[TKind::SquarenRT])?;
 		array.kind = Kind::Array;
 		self.eat(TKind::SquarenRT)?;
+		array.meta = Span::new(start, self.end());
 		Ok(array)
 	}
 
-	fn integer(&mut self) -> Result<Element, String> {
+	fn integer(&mut self) -> Result<Element, Diagnostic> {
 		let token = self.eat(TKind::Integer)?;
 		let mut number = Element::new(Kind::Integer);
+		number.meta = token.span();
 		number.text = token.text.clone();
+		number.literal = Some(self.literal(&number)?);
 		Ok(number)
 	}
 
-	fn decimal(&mut self) -> Result<Element, String> {
+	fn decimal(&mut self) -> Result<Element, Diagnostic> {
 		let token = self.eat(TKind::Decimal)?;
 		let mut number = Element::new(Kind::Decimal);
+		number.meta = token.span();
 		number.text = token.text.clone();
+		number.literal = Some(self.literal(&number)?);
 		Ok(number)
 	}
+
+	fn string(&mut self) -> Result<Element, Diagnostic> {
+		let token = self.eat(TKind::String)?;
+		let mut element = Element::new(Kind::String);
+		element.meta = token.span();
+		element.text = token.text.clone();
+		let bytes = string(&element.text).map_err(|message| {
+			Diagnostic::error(diagnostic::INVALID_LITERAL, message, element.meta)
+				.label("not a valid string".to_string())
+		})?;
+		element.literal = Some(Literal {
+			value: Value::Bytes(bytes),
+			width: None,
+		});
+		Ok(element)
+	}
+
+	fn literal(&self, number: &Element) -> Result<Literal, Diagnostic> {
+		literal(&number.text).map_err(|message| {
+			Diagnostic::error(diagnostic::INVALID_LITERAL, message, number.meta)
+				.label("not a valid number".to_string())
+		})
+	}
 }
 
 impl State {
-	fn eat(&mut self, kind: TKind) -> Result<&Token, String> {
+	fn eat(&mut self, kind: TKind) -> Result<&Token, Diagnostic> {
 		match self.get(0) {
 			Some(t) => {
-				*self.cursor.borrow_mut() += 1;
 				if t.kind == kind {
+					*self.cursor.borrow_mut() += 1;
 					Ok(t)
 				} else {
-					Err(format!(
-						"UnexpectedToken: {:?} of {:?} on line {}\nExpected token of name: {:?}",
-						t.text, t.kind, t.meta.row, kind
-					))
+					Err(Diagnostic::error(
+						diagnostic::UNEXPECTED_TOKEN,
+						format!("unexpected token {:?} of {:?}", t.text, t.kind),
+						t.span(),
+					)
+					.label(format!("expected {:?}", kind)))
 				}
 			}
-			None => Err("UnexpectedEndOfInput".to_string()),
+			None => Err(Diagnostic::error(
+				diagnostic::UNEXPECTED_END,
+				"unexpected end of input".to_string(),
+				Span::new(self.end(), self.end()),
+			)
+			.label(format!("expected {:?}", kind))),
+		}
+	}
+
+	// start of the current token
+	fn start(&self) -> Meta {
+		match self.get(0) {
+			Some(t) => t.meta,
+			None => self.end(),
+		}
+	}
+
+	// from start to the end of the most recently consumed token, empty at
+	// start when nothing was consumed since
+	fn since(&self, start: Meta) -> Span {
+		let end = self.end();
+		if end.offset < start.offset {
+			Span::new(start, start)
+		} else {
+			Span::new(start, end)
+		}
+	}
+
+	// end of the most recently consumed token
+	fn end(&self) -> Meta {
+		let cursor = *self.cursor.borrow();
+		if cursor == 0 {
+			Meta::default()
+		} else {
+			self.tokens[cursor.min(self.tokens.len()) - 1].end
 		}
 	}
 
@@ -404,7 +953,7 @@ impl State {
 			None => false,
 		}
 	}
-	fn _any(&self, offset: usize, kinds: &[TKind]) -> bool {
+	fn any(&self, offset: usize, kinds: &[TKind]) -> bool {
 		for kind in kinds {
 			if self.is(offset, *kind) {
 				return true;
@@ -427,3 +976,154 @@ impl State {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::super::tokenizer;
+	use super::*;
+
+	fn parse(source: &str) -> (NMap, Vec<&'static str>) {
+		parse_shadowing(source, false)
+	}
+
+	fn parse_shadowing(source: &str, shadowing: bool) -> (NMap, Vec<&'static str>) {
+		let (tokens, _) = tokenizer::tokenizer(source);
+		let (nmap, _, _, diagnostics) = parser("test", tokens, shadowing);
+		(nmap, diagnostics.iter().map(|d| d.code).collect())
+	}
+
+	fn value(text: &str) -> (Value, Option<Type>) {
+		let literal = literal(text).unwrap();
+		(literal.value, literal.width)
+	}
+
+	#[test]
+	fn integers() {
+		assert_eq!(value("10"), (Value::Integer(10), None));
+		assert_eq!(value("1_000"), (Value::Integer(1000), None));
+		assert_eq!(value("0xFF"), (Value::Integer(255), None));
+		assert_eq!(value("0x_ff"), (Value::Integer(255), None));
+		assert_eq!(value("0b1010"), (Value::Integer(10), None));
+		assert_eq!(value("18446744073709551615"), (Value::Integer(u64::MAX), None));
+		assert!(literal("18446744073709551616").is_err());
+		assert!(literal("0x+F").is_err());
+		assert!(literal("0b102").is_err());
+		assert!(literal("0x").is_err());
+	}
+
+	#[test]
+	fn suffixes() {
+		assert_eq!(value("10i32"), (Value::Integer(10), typer::base("i32")));
+		assert_eq!(value("1_000u16"), (Value::Integer(1000), typer::base("u16")));
+		assert_eq!(value("10f64"), (Value::Integer(10), typer::base("f64")));
+		assert_eq!(value("0.9f32"), (Value::Decimal(0.9), typer::base("f32")));
+		assert_eq!(value("1.5c64"), (Value::Decimal(1.5), typer::base("c64")));
+		assert!(literal("1.5i32").is_err());
+		assert!(literal("10i7").is_err());
+		assert!(literal("10x32").is_err());
+	}
+
+	#[test]
+	fn hex_digits_are_not_suffixes() {
+		// f and c are digits after 0x, only i and u start a suffix
+		assert_eq!(value("0x1f"), (Value::Integer(0x1f), None));
+		assert_eq!(value("0xFFf32"), (Value::Integer(0xfff32), None));
+		assert_eq!(value("0xc64"), (Value::Integer(0xc64), None));
+		assert_eq!(value("0xFFu8"), (Value::Integer(255), typer::base("u8")));
+		assert_eq!(value("0x7Fi8"), (Value::Integer(127), typer::base("i8")));
+	}
+
+	#[test]
+	fn decimals() {
+		assert_eq!(value("0.5"), (Value::Decimal(0.5), None));
+		assert_eq!(value("1."), (Value::Decimal(1.0), None));
+		assert_eq!(value("1_0.2_5"), (Value::Decimal(10.25), None));
+		assert_eq!(value("1e3"), (Value::Decimal(1000.0), None));
+		assert_eq!(value("1.5e-3"), (Value::Decimal(0.0015), None));
+		assert_eq!(value("2E+2f64"), (Value::Decimal(200.0), typer::base("f64")));
+		assert!(literal("1e999").is_err());
+	}
+
+	#[test]
+	fn escapes() {
+		assert_eq!(string(r#""a\n\t\r\0\\\"""#), Ok(b"a\n\t\r\0\\\"".to_vec()));
+		assert_eq!(string(r#""\xFF\u{2192}""#), Ok(vec![0xFF, 0xE2, 0x86, 0x92]));
+		assert_eq!(string(r#""→""#), Ok("→".as_bytes().to_vec()));
+		assert!(string(r#""\q""#).is_err());
+		assert!(string(r#""\x4""#).is_err());
+		assert!(string(r#""\u{110000}""#).is_err());
+		assert!(string(r#""\u41""#).is_err());
+	}
+
+	#[test]
+	fn strings_are_dumped_without_loss() {
+		let source = r#""a\"\\\n\xFF\x01\u{2192}""#;
+		let literal = Literal {
+			value: Value::Bytes(string(source).unwrap()),
+			width: None,
+		};
+		let dumped = format!("{:?}", literal);
+		assert_eq!(dumped, r#""a\"\\\n\xFF\u{1}→""#);
+		assert_eq!(string(&dumped), string(source));
+	}
+
+	#[test]
+	fn escapes_take_hex_digits_only() {
+		assert_eq!(string(r#""\u{41}\x42""#), Ok(b"AB".to_vec()));
+		assert!(string(r#""\u{+41}""#).is_err());
+		assert!(string(r#""\u{}""#).is_err());
+		assert!(string(r#""\x+4""#).is_err());
+	}
+
+	#[test]
+	fn empty_stacks_have_an_empty_span() {
+		let (tokens, _) = tokenizer::tokenizer("a;\nb; 1\n");
+		let (_, emap, _, _) = parser("test", tokens, false);
+		let span = emap.get("test.a").unwrap().meta;
+		assert_eq!((span.start.offset, span.end.offset), (3, 3));
+	}
+
+	#[test]
+	fn graphs_after_a_colon() {
+		for source in ["result: {\n\ta; 1\n}\n", "result: f32, { a; 1.5 }\n"] {
+			let (nmap, codes) = parse(source);
+			assert!(codes.is_empty(), "{}", source);
+			let result = nmap.index("test.result").unwrap();
+			assert_eq!(nmap.at(result).node, Node::Graph);
+			assert!(lookup(&nmap, Some(result), "result.a").is_some());
+		}
+	}
+
+	#[test]
+	fn duplicate_keys() {
+		assert_eq!(parse("a; 1\na; 2\n").1, vec![diagnostic::DUPLICATE_KEY]);
+		assert_eq!(parse("a.{ b; 1 }\na.{ b; 2 }\n").1, vec![diagnostic::DUPLICATE_KEY]);
+		assert!(parse("a.{ b; 1 }\nc.{ b; 2 }\n").1.is_empty());
+	}
+
+	#[test]
+	fn shadowing_drops_the_earlier_definition() {
+		let (nmap, codes) = parse_shadowing("a.{ b; 1  c.{ d; 2 } }\ne; 3\na.{ f; 4 }\n", true);
+		assert!(codes.is_empty());
+		for key in ["test.a.b", "test.a.c", "test.a.c.d"] {
+			assert!(!nmap.contains_key(key), "{}", key);
+		}
+		let [test, a, e, f] = ["test", "test.a", "test.e", "test.a.f"].map(|k| nmap.index(k).unwrap());
+		assert_eq!(nmap.at(a).keys, vec![f]);
+		assert_eq!(nmap.at(f).parent, Some(a));
+		assert_eq!(nmap.at(test).keys, vec![e, a]);
+	}
+
+	#[test]
+	fn networks_link_to_their_graph() {
+		let (nmap, codes) = parse("a.b; 1\nc; a.b\n");
+		assert!(codes.is_empty());
+		let b = nmap.index("test.a.b").unwrap();
+		assert_eq!(nmap.at(b).parent, nmap.index("test"));
+		let (nmap, codes) = parse("a.{ b; 1 }\nc; a.b\n");
+		assert!(codes.is_empty());
+		let b = nmap.index("test.a.b").unwrap();
+		assert_eq!(nmap.at(b).parent, nmap.index("test.a"));
+		assert_eq!(lookup(&nmap, nmap.index("test.c"), "a.b"), Some(b));
+	}
+}
@@ -1,10 +1,13 @@
+use super::diagnostic::{self, Diagnostic};
 use lazy_static::lazy_static;
 use regex::Regex;
+use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Kind {
    Invalid,
    Skip,
+   Comment,
    Newline,
    //
    String,
@@ -13,7 +16,7 @@ pub enum Kind {
    Decimal,
 
    //
-   // Arrow, // ->
+   Arrow, // ->
    ParenLF,
    ParenRT,
    SquarenLF,
@@ -33,19 +36,73 @@ pub enum Kind {
    Dot,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Meta {
    pub row: usize,
    pub col: usize,
+   pub offset: usize,
+}
+
+// start and end position of a piece of source, end is exclusive
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Span {
+   pub start: Meta,
+   pub end: Meta,
+}
+
+impl Span {
+   pub fn new(start: Meta, end: Meta) -> Span {
+      Span { start, end }
+   }
+
+   pub fn join(&self, other: &Span) -> Span {
+      Span {
+         start: self.start,
+         end: other.end,
+      }
+   }
+}
+
+// kept on a single line so that stage dumps stay readable
+impl fmt::Debug for Span {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(
+         f,
+         "{}:{}-{}:{} ({}..{})",
+         self.start.row,
+         self.start.col,
+         self.end.row,
+         self.end.col,
+         self.start.offset,
+         self.end.offset
+      )
+   }
 }
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
    pub kind: Kind,
    pub text: String,
    pub meta: Meta,
+   pub end: Meta,
+}
+
+impl Token {
+   pub fn span(&self) -> Span {
+      Span::new(self.meta, self.end)
+   }
 }
 
-pub fn tokenizer(input: &String) -> Result<Vec<Token>, String> {
+pub fn tokenizer(input: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+   tokenize(input, false)
+}
+
+// also keeps whitespace, comments and newlines, so the source can be rebuilt
+pub fn trivia(input: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+   tokenize(input, true)
+}
+
+fn tokenize(input: &str, trivia: bool) -> (Vec<Token>, Vec<Diagnostic>) {
    lazy_static! {
       static ref SPEC: Vec<(Kind, Regex)> =
          vec![
@@ -54,22 +111,22 @@ pub fn tokenizer(input: &String) -> Result<Vec<Token>, String> {
             (Kind::Skip, Regex::new(r"^[\t\v\f\r ]+").unwrap()),
 
             // Comments
-            (Kind::Skip, Regex::new(r"^;;.*").unwrap()),
+            (Kind::Comment, Regex::new(r"^;;.*").unwrap()),
 
             (Kind::Arrow, Regex::new(r"^(→|->)").unwrap()),
             (Kind::Post, Regex::new(r"^\|").unwrap()),
 
             // (Kind::Number, Regex::new(r"^[[:digit:]]([^[:space:]|{}()\[\]])*").unwrap()),
 
-            (Kind::Net, Regex::new(r"^([^[:space:].|{}()\[\]])+[.][[:space:]]*\{").unwrap()),
+            (Kind::Net, Regex::new(r#"^([^[:space:][:cntrl:]\p{Z}\p{Cf}".|{}()\[\]])+[.][[:space:]]*\{"#).unwrap()),
             // (Kind::Net, Regex::new(r"^([^[:space:].|{}()\[\]])+[.]").unwrap()),
 
             (Kind::Com, Regex::new(r"^,").unwrap()),
-            (Kind::Dot, Regex::new(r"^\.([^[:space:],|{}()\[\]])+").unwrap()),
-            (Kind::Typ, Regex::new(r"^([^[:space:],|{}()\[\]])+:").unwrap()),
-            (Kind::Key, Regex::new(r"^([^[:space:],|{}()\[\]])+;").unwrap()),
+            (Kind::Dot, Regex::new(r#"^\.([^[:space:][:cntrl:]\p{Z}\p{Cf}",|{}()\[\]])+"#).unwrap()),
+            (Kind::Typ, Regex::new(r#"^([^[:space:][:cntrl:]\p{Z}\p{Cf}",|{}()\[\]])+:"#).unwrap()),
+            (Kind::Key, Regex::new(r#"^([^[:space:][:cntrl:]\p{Z}\p{Cf}",|{}()\[\]])+;"#).unwrap()),
             // (Kind::Dot, Regex::new(r"^\.").unwrap()),
-            (Kind::Ref, Regex::new(r"^([^[:space:],|{}()\[\]])+").unwrap()),
+            (Kind::Ref, Regex::new(r#"^([^[:space:][:cntrl:]\p{Z}\p{Cf}",|{}()\[\]])+"#).unwrap()),
 
 
 
@@ -85,25 +142,34 @@ pub fn tokenizer(input: &String) -> Result<Vec<Token>, String> {
             (Kind::BracketRT, Regex::new(r"^\}").unwrap()),
 
 
-            (Kind::String, Regex::new(r#"^"[^"]*("|$)"#).unwrap()),
-
+            // strings end at the closing quote or, when unterminated, at the end of the line,
+            // a backslash escapes the character after it unless the line ends there
+            (Kind::String, Regex::new(r#"(?m)\A"([^"\\\n]|\\.)*("|\\?$)"#).unwrap()),
 
 
+            // control, format and separator characters are reported, not tokenized
             (Kind::Invalid, Regex::new(r"^.").unwrap()),
          ];
    }
-   // decide what is a word aka reference and what is a number
+   // decide what is a word aka reference and what is a number, the parser
+   // reads the value and reports malformed numbers
    lazy_static! {
       static ref WORD: Vec<(Kind, Regex)> = vec![
          (
             Kind::Decimal,
-            Regex::new(r"^[[:digit:]]+\.[[:digit:]]*$").unwrap()
+            Regex::new(r"^[[:digit:]][[:digit:]_]*\.[[:digit:]_]*([eE][+-]?[[:digit:]_]+)?([a-z][[:digit:]]+)?$").unwrap()
+         ),
+         (
+            Kind::Decimal,
+            Regex::new(r"^[[:digit:]]*\.[[:digit:]][[:digit:]_]*([eE][+-]?[[:digit:]_]+)?([a-z][[:digit:]]+)?$").unwrap()
          ),
+         // `1e-3`
          (
             Kind::Decimal,
-            Regex::new(r"^[[:digit:]]*\.[[:digit:]]+$").unwrap()
+            Regex::new(r"^[[:digit:]][[:digit:]_]*[eE][+-]?[[:digit:]_]+([a-z][[:digit:]]+)?$").unwrap()
          ),
-         (Kind::Integer, Regex::new(r"^[[:digit:]]+$").unwrap()),
+         // `10`, `0xFF`, `0b1010`, `10i32`, and words that only start like a number
+         (Kind::Integer, Regex::new(r"^[[:digit:]]").unwrap()),
          // (Kind::Ref, Regex::new(r"^.").unwrap()),
       ];
    }
@@ -116,6 +182,9 @@ pub fn tokenizer(input: &String) -> Result<Vec<Token>, String> {
    }
 
    let mut tokens: Vec<Token> = Vec::new();
+   let mut diagnostics: Vec<Diagnostic> = Vec::new();
+   // run of adjacent invalid characters, reported as one
+   let mut invalid: Option<Token> = None;
    let mut cursor = 0;
    let mut row = 1;
    let mut col = 1;
@@ -123,62 +192,179 @@ pub fn tokenizer(input: &String) -> Result<Vec<Token>, String> {
 
    'outer: while cursor < length {
       for (kind, re) in &SPEC[..] {
-         match re.find(&input[cursor..]) {
-            Some(mat) => {
-               let token_text = &input[cursor..cursor + mat.end()];
-               let text = token_text.to_string();
-               let mut t = Token {
-                  kind: *kind,
-                  text,
-                  meta: Meta { col, row },
-               };
-               col += mat.end();
-
-               match kind {
-                  Kind::Newline => {
-                     row += 1;
-                     col = 1;
-                  }
-                  Kind::Skip => {}
-                  Kind::Ref => {
-                     // let x = true;
-                     for (kind, re) in &WORD[..] {
-                        if re.is_match(&t.text) {
-                           t.kind = *kind;
-                           break;
-                        }
-                     }
-                     tokens.push(t);
-                  }
-                  Kind::Key => {
-                     t.text = t.text[..t.text.len() - 1].to_string();
+         if let Some(mat) = re.find(&input[cursor..]) {
+            let length = mat.end();
+            let unterminated = *kind == Kind::String && !closed(&input[cursor..cursor + length]);
+            let token_text = &input[cursor..cursor + length];
+            let text = token_text.to_string();
+            let meta = Meta {
+               col,
+               row,
+               offset: cursor,
+            };
+            match token_text.rfind('\n') {
+               Some(newline) if *kind != Kind::Newline => {
+                  row += token_text.matches('\n').count();
+                  col = token_text[newline + 1..].chars().count() + 1;
+               }
+               _ => col += token_text.chars().count(),
+            }
+            let mut t = Token {
+               kind: *kind,
+               text,
+               meta,
+               end: Meta {
+                  col,
+                  row,
+                  offset: cursor + length,
+               },
+            };
+
+            if *kind != Kind::Invalid {
+               if let Some(run) = invalid.take() {
+                  diagnostics.push(invalid_characters(run));
+               }
+            }
+
+            match kind {
+               Kind::Newline => {
+                  row += 1;
+                  col = 1;
+                  if trivia {
+                     t.end = Meta { row, col, offset: cursor + length };
                      tokens.push(t);
                   }
-                  Kind::Typ => {
-                     t.text = t.text[..t.text.len() - 1].to_string();
+               }
+               Kind::Skip | Kind::Comment => {
+                  if trivia {
                      tokens.push(t);
                   }
-                  // Kind::Net => {
-                  //    t.text = t.text[..t.text.len() - 1].to_string();
-                  //    tokens.push(t);
-                  // }
-                  Kind::Net => {
-                     let (kind, re) = &NETWORK[0];
-                     let mat = re.find(&t.text).unwrap();
-                     t.text = t.text[..mat.end()].to_string();
-                     tokens.push(t);
+               }
+               Kind::Invalid => match &mut invalid {
+                  Some(run) => {
+                     run.text.push_str(&t.text);
+                     run.end = t.end;
                   }
-                  _ => {
-                     tokens.push(t);
+                  None => invalid = Some(t),
+               },
+               Kind::String if unterminated => {
+                  diagnostics.push(
+                     Diagnostic::error(
+                        diagnostic::UNTERMINATED_STRING,
+                        "unterminated string".to_string(),
+                        t.span(),
+                     )
+                     .label("missing closing quote".to_string()),
+                  );
+               }
+               Kind::Ref => {
+                  // let x = true;
+                  for (kind, re) in &WORD[..] {
+                     if re.is_match(&t.text) {
+                        t.kind = *kind;
+                        break;
+                     }
                   }
+                  tokens.push(t);
+               }
+               Kind::Key => {
+                  t.text = t.text[..t.text.len() - 1].to_string();
+                  tokens.push(t);
+               }
+               Kind::Typ => {
+                  t.text = t.text[..t.text.len() - 1].to_string();
+                  tokens.push(t);
+               }
+               // Kind::Net => {
+               //    t.text = t.text[..t.text.len() - 1].to_string();
+               //    tokens.push(t);
+               // }
+               Kind::Net => {
+                  let (kind, re) = &NETWORK[0];
+                  let mat = re.find(&t.text).unwrap();
+                  t.text = t.text[..mat.end()].to_string();
+                  tokens.push(t);
+               }
+               _ => {
+                  tokens.push(t);
                }
-
-               cursor += mat.end();
-               continue 'outer;
             }
-            None => {}
+
+            cursor += length;
+            continue 'outer;
          }
       }
    }
-   Ok(tokens)
+   if let Some(run) = invalid.take() {
+      diagnostics.push(invalid_characters(run));
+   }
+   (tokens, diagnostics)
+}
+
+// whether a string token ends with a quote that is not escaped
+fn closed(text: &str) -> bool {
+   let inner = match text.strip_suffix('"') {
+      Some(inner) if text.len() > 1 => inner,
+      _ => return false,
+   };
+   let escapes = inner.chars().rev().take_while(|c| *c == '\\').count();
+   escapes % 2 == 0
+}
+
+fn invalid_characters(run: Token) -> Diagnostic {
+   let count = run.text.chars().count();
+   Diagnostic::error(
+      diagnostic::INVALID_CHARACTER,
+      format!(
+         "invalid character{} {:?}",
+         if count > 1 { "s" } else { "" },
+         run.text
+      ),
+      run.span(),
+   )
+   .label("not valid in source".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn kinds(source: &str) -> (Vec<Kind>, Vec<&'static str>) {
+      let (tokens, diagnostics) = tokenizer(source);
+      (
+         tokens.iter().map(|t| t.kind).collect(),
+         diagnostics.iter().map(|d| d.code).collect(),
+      )
+   }
+
+   #[test]
+   fn numbers() {
+      for word in ["10", "1_000", "0xFF", "0b1010", "10i32", "0xFFu8"] {
+         assert_eq!(kinds(word), (vec![Kind::Integer], vec![]), "{}", word);
+      }
+      for word in ["0.5", "1.", "1_0.2_5", "1e3", "1E-3", "1.5e+3f32", "0.9f32", "2e2c64"] {
+         assert_eq!(kinds(word), (vec![Kind::Decimal], vec![]), "{}", word);
+      }
+      // words that start like a number are left to the parser to reject
+      assert_eq!(kinds("1.5.2"), (vec![Kind::Integer], vec![]));
+      assert_eq!(kinds("1e"), (vec![Kind::Integer], vec![]));
+      // `.5` is a pun
+      assert_eq!(kinds(".5"), (vec![Kind::Dot], vec![]));
+      assert_eq!(kinds("x10"), (vec![Kind::Ref], vec![]));
+   }
+
+   #[test]
+   fn strings() {
+      assert_eq!(kinds(r#""abc""#), (vec![Kind::String], vec![]));
+      assert_eq!(kinds(r#""a\"b""#), (vec![Kind::String], vec![]));
+      assert_eq!(kinds(r#""a\\""#), (vec![Kind::String], vec![]));
+      assert_eq!(kinds("\"abc"), (vec![], vec![diagnostic::UNTERMINATED_STRING]));
+      assert_eq!(kinds("\"abc\\\"\nb; 1"), (vec![Kind::Key, Kind::Integer], vec![diagnostic::UNTERMINATED_STRING]));
+   }
+
+   #[test]
+   fn strings_ending_with_a_backslash() {
+      assert_eq!(kinds("\"abc\\"), (vec![], vec![diagnostic::UNTERMINATED_STRING]));
+      assert_eq!(kinds("\"abc\\\nb; 1"), (vec![Kind::Key, Kind::Integer], vec![diagnostic::UNTERMINATED_STRING]));
+   }
 }
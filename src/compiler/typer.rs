@@ -1 +1,1014 @@
+use super::builtin::{self, Class, Operator};
+use super::diagnostic::{self, Diagnostic};
+use super::parser::{module, EMap, Element, Index, Kind, NMap, Node, TMap, Value};
+use super::tokenizer::Span;
+use std::collections::HashMap;
+use std::fmt;
+
 // typer executes the program graph using type reduction
+
+// the only types are base widths, `i32 f32 c64`, and arrays, tuples and
+// functions of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+	Signed,
+	Unsigned,
+	Float,
+	Complex,
+}
+
+impl Format {
+	fn letter(&self) -> char {
+		match self {
+			Format::Signed => 'i',
+			Format::Unsigned => 'u',
+			Format::Float => 'f',
+			Format::Complex => 'c',
+		}
+	}
+
+	fn widths(&self) -> &'static [usize] {
+		match self {
+			Format::Signed | Format::Unsigned => &[8, 16, 32, 64],
+			Format::Float => &[32, 64],
+			Format::Complex => &[64],
+		}
+	}
+}
+
+const FORMATS: [Format; 4] = [Format::Signed, Format::Unsigned, Format::Float, Format::Complex];
+
+#[derive(Clone, PartialEq)]
+pub enum Type {
+	// nothing is known, and nothing is checked against it
+	Unknown,
+	// numeric literals, until a declared type gives them a width
+	Integer,
+	Decimal,
+	// `i32`
+	Base(Format, usize),
+	// `x32` is any format of the width, `fx` any width of the format
+	Width(usize),
+	Format(Format),
+	// a type written in a signature that is not a base type
+	Named(String),
+	Array(Box<Type>),
+	Tuple(Vec<Type>),
+	// inputs and outputs
+	Function(Vec<Type>, Vec<Type>),
+}
+
+impl fmt::Display for Type {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Type::Unknown => write!(f, "?"),
+			Type::Integer => write!(f, "integer"),
+			Type::Decimal => write!(f, "decimal"),
+			Type::Base(format, width) => write!(f, "{}{}", format.letter(), width),
+			Type::Width(width) => write!(f, "x{}", width),
+			Type::Format(format) => write!(f, "{}x", format.letter()),
+			Type::Named(name) => write!(f, "{}", name),
+			Type::Array(item) => write!(f, "[{}]", item),
+			Type::Tuple(items) => write!(f, "({})", words(items)),
+			Type::Function(inputs, outputs) => write!(f, "({} -> {})", words(inputs), words(outputs)),
+		}
+	}
+}
+
+// dumps show a type the way it is written, `(i32 -> f32)`
+impl fmt::Debug for Type {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self)
+	}
+}
+
+impl Type {
+	fn is_base(&self) -> bool {
+		matches!(self, Type::Base(..) | Type::Width(_) | Type::Format(_))
+	}
+
+	fn is_wildcard(&self) -> bool {
+		matches!(self, Type::Width(_) | Type::Format(_))
+	}
+
+	// base types and literals, or arrays of them
+	fn is_numeric(&self) -> bool {
+		match self {
+			Type::Integer | Type::Decimal => true,
+			Type::Array(item) => item.is_numeric(),
+			typ => typ.is_base(),
+		}
+	}
+
+	// whether a decimal literal can have this type
+	fn is_fractional(&self) -> bool {
+		match self {
+			Type::Base(format, _) | Type::Format(format) => {
+				*format == Format::Float || *format == Format::Complex
+			}
+			Type::Width(width) => [Format::Float, Format::Complex]
+				.iter()
+				.any(|f| f.widths().contains(width)),
+			Type::Named(_) => true,
+			_ => false,
+		}
+	}
+
+	// whether a wildcard stands for the base type
+	fn admits(&self, format: Format, width: usize) -> bool {
+		match self {
+			Type::Width(w) => *w == width,
+			Type::Format(f) => *f == format,
+			_ => false,
+		}
+	}
+}
+
+// `i32`, the wildcards `x32` and `fx`, and the aliases `R`, `Z` and `N` for
+// any width of real, integer and natural numbers
+pub fn base(name: &str) -> Option<Type> {
+	match name {
+		"R" => return Some(Type::Format(Format::Float)),
+		"Z" => return Some(Type::Format(Format::Signed)),
+		"N" => return Some(Type::Format(Format::Unsigned)),
+		_ => {}
+	}
+	let mut chars = name.chars();
+	let letter = chars.next()?;
+	let rest = chars.as_str();
+	if letter == 'x' {
+		let width = rest.parse().ok()?;
+		return match FORMATS.iter().any(|f| f.widths().contains(&width)) {
+			true => Some(Type::Width(width)),
+			false => None,
+		};
+	}
+	let format = *FORMATS.iter().find(|f| f.letter() == letter)?;
+	if rest == "x" {
+		return Some(Type::Format(format));
+	}
+	let width = rest.parse().ok()?;
+	match format.widths().contains(&width) {
+		true => Some(Type::Base(format, width)),
+		false => None,
+	}
+}
+
+// `i7` or `f16` look like base types but are not one
+fn malformed(name: &str) -> bool {
+	let mut chars = name.chars();
+	let letter = chars.next();
+	let rest = chars.as_str();
+	matches!(letter, Some('i' | 'u' | 'f' | 'c' | 'x'))
+		&& !rest.is_empty()
+		&& rest.chars().all(|c| c.is_ascii_digit())
+		&& base(name).is_none()
+}
+
+fn words(types: &[Type]) -> String {
+	types.iter().map(Type::to_string).collect::<Vec<String>>().join(" ")
+}
+
+// the values a stack holds, with the span of the element that pushed each
+struct Stack {
+	values: Vec<(Type, Span)>,
+	// values taken from below the bottom of the stack, the inputs of its effect
+	consumed: usize,
+	// the first element that took more values than the stack held
+	underflow: Option<Underflow>,
+	// false once an element whose effect is not known was pushed
+	known: bool,
+}
+
+struct Underflow {
+	span: Span,
+	name: String,
+	takes: usize,
+	holds: usize,
+}
+
+impl Stack {
+	fn new() -> Stack {
+		Stack {
+			values: Vec::new(),
+			consumed: 0,
+			underflow: None,
+			known: true,
+		}
+	}
+
+	fn types(&self) -> Vec<Type> {
+		types(&self.values)
+	}
+
+	// the values an element takes, the ones missing from the stack are inputs
+	fn pop(&mut self, count: usize, element: &Element) -> Vec<(Type, Span)> {
+		let holds = self.values.len();
+		let missing = count.saturating_sub(holds);
+		if missing > 0 && self.underflow.is_none() {
+			self.underflow = Some(Underflow {
+				span: element.meta,
+				name: element.text.clone(),
+				takes: count,
+				holds,
+			});
+		}
+		self.consumed += missing;
+		let mut values = vec![(Type::Unknown, element.meta); missing];
+		values.extend(self.values.drain(holds - (count - missing)..));
+		values
+	}
+
+	// a stack that takes values is a function, one that only pushes is its values
+	fn effect(&self) -> Type {
+		if self.consumed > 0 {
+			Type::Function(vec![Type::Unknown; self.consumed], self.types())
+		} else {
+			single(self.types())
+		}
+	}
+}
+
+// `e: f64 -> f64` declares the inputs and the outputs of e
+struct Signature {
+	inputs: Vec<(Type, Span)>,
+	outputs: Vec<(Type, Span)>,
+	span: Span,
+}
+
+struct State {
+	nmap: NMap,
+	emap: EMap,
+	tmap: TMap,
+	// point types worked out so far, None while the point is being typed
+	types: HashMap<String, Option<Type>>,
+	// the type the numeric literals of the point being typed take, and where
+	// it is declared
+	context: Option<(Type, Span)>,
+	// the declared types of the parameters of the function being typed
+	parameters: HashMap<String, Type>,
+	// the base type each wildcard stands for in a graph, and where it was bound
+	bindings: HashMap<(Option<Index>, String), (Type, Span)>,
+	diagnostics: Vec<Diagnostic>,
+}
+
+// Work out the type of every point from its stack, check it against the
+// signature the point declares and note it on the point's element.
+pub fn parser(nmap: NMap, emap: EMap, tmap: TMap) -> (NMap, EMap, TMap, Vec<Diagnostic>) {
+	let mut state = State {
+		nmap,
+		emap,
+		tmap,
+		types: HashMap::new(),
+		context: None,
+		parameters: HashMap::new(),
+		bindings: HashMap::new(),
+		diagnostics: Vec::new(),
+	};
+
+	for (key, element) in &state.tmap {
+		widths(module(&state.nmap, key), element, &mut state.diagnostics);
+	}
+
+	let keys: Vec<String> = state.emap.keys().cloned().collect();
+	for key in &keys {
+		state.point(key);
+	}
+
+	(state.nmap, state.emap, state.tmap, state.diagnostics)
+}
+
+impl State {
+	// None while the point is being typed, it refers back to itself
+	fn point(&mut self, key: &String) -> Option<Type> {
+		if let Some(typ) = self.types.get(key) {
+			return typ.clone();
+		}
+		let mut element = match self.emap.get(key) {
+			Some(element) => element.clone(),
+			None => return Some(Type::Unknown),
+		};
+		self.types.insert(key.clone(), None);
+		let scope = self.nmap.index(key).and_then(|i| self.nmap.at(i).parent);
+
+		let signature = self
+			.tmap
+			.get(key)
+			.map(signature)
+			.filter(|s| !s.inputs.is_empty() || !s.outputs.is_empty());
+		// a point without a signature has the type of the nearest typed graph
+		let constraint = match signature {
+			Some(_) => None,
+			None => self.constraint(scope),
+		};
+		let context = match (&signature, &constraint) {
+			(Some(signature), _) => match signature.outputs.as_slice() {
+				[(typ, span)] if typ.is_base() => Some((self.resolve(scope, typ), *span)),
+				_ => None,
+			},
+			(None, Some((typ, span, graph))) => Some((self.resolve(*graph, typ), *span)),
+			_ => None,
+		};
+
+		// a function body under a signature takes the inputs as its parameters,
+		// as in `add: i32 i32 -> i32, |x y| x y +`
+		let function = matches!(
+			(&signature, element.body.as_slice()),
+			(Some(_), [body]) if body.kind == Kind::Function
+		);
+		let head = match function {
+			true => element.body[0].head.clone(),
+			false => Vec::new(),
+		};
+
+		// the inputs of a point are on its stack before its elements
+		let mut stack = Stack::new();
+		let mut parameters = HashMap::new();
+		if let Some(signature) = &signature {
+			for (parameter, (typ, _)) in head.iter().zip(&signature.inputs) {
+				parameters.insert(parameter.text.clone(), typ.clone());
+			}
+			stack.values = signature.inputs[head.len().min(signature.inputs.len())..].to_vec();
+			if head.len() > signature.inputs.len() {
+				self.arity(key, signature, &head);
+			}
+		}
+		let outer = std::mem::replace(&mut self.context, context);
+		let outer_parameters = std::mem::replace(&mut self.parameters, parameters);
+		let elements = match function {
+			true => &mut element.body[0].body,
+			false => &mut element.body,
+		};
+		for child in elements.iter_mut() {
+			self.push(key, child, &mut stack);
+		}
+		self.context = outer;
+		self.parameters = outer_parameters;
+		if let (true, Some(signature)) = (function, &signature) {
+			let outputs = if stack.known { stack.types() } else { vec![Type::Unknown] };
+			element.body[0].typ = Type::Function(types(&signature.inputs), outputs);
+		}
+
+		// a point without elements is an input of its graph
+		if stack.known && !element.body.is_empty() {
+			if let Some(signature) = &signature {
+				self.effect(key, signature, &stack, element.meta);
+				self.check(key, scope, signature, &stack);
+			}
+			if let Some((typ, span, graph)) = &constraint {
+				self.constrain(key, *graph, typ, *span, &stack);
+			}
+		}
+
+		let typ = match (&signature, &constraint) {
+			(Some(signature), _) => {
+				let outputs = types(&signature.outputs).iter().map(|t| self.resolve(scope, t)).collect();
+				match signature.inputs.is_empty() {
+					true => single(outputs),
+					false => Type::Function(types(&signature.inputs), outputs),
+				}
+			}
+			(None, Some((typ, _, graph))) if element.body.is_empty() => self.resolve(*graph, typ),
+			_ if stack.known => stack.effect(),
+			_ => Type::Unknown,
+		};
+		element.typ = typ.clone();
+		self.emap.insert(key.clone(), element);
+		self.types.insert(key.clone(), Some(typ.clone()));
+		Some(typ)
+	}
+
+	// the base type the nearest typed graph around the scope declares
+	fn constraint(&self, scope: Option<Index>) -> Option<(Type, Span, Option<Index>)> {
+		let mut graph = scope;
+		while let Some(g) = graph {
+			if let Some(element) = self.tmap.get(self.nmap.key(g)) {
+				let signature = signature(element);
+				if let ([], [(typ, span)]) = (signature.inputs.as_slice(), signature.outputs.as_slice()) {
+					if typ.is_base() {
+						return Some((typ.clone(), *span, Some(g)));
+					}
+				}
+			}
+			graph = self.nmap.at(g).parent;
+		}
+		None
+	}
+
+	// the base type a wildcard is bound to in the graph
+	fn resolve(&self, scope: Option<Index>, typ: &Type) -> Type {
+		match self.bindings.get(&(scope, typ.to_string())) {
+			Some((bound, _)) if typ.is_wildcard() => bound.clone(),
+			_ => typ.clone(),
+		}
+	}
+
+	// every use of a wildcard in a graph stands for the same base type, false
+	// when it already stands for another
+	fn bind(&mut self, key: &str, scope: Option<Index>, wildcard: &Type, typ: &Type, span: Span) -> bool {
+		let name = wildcard.to_string();
+		match self.bindings.get(&(scope, name.clone())) {
+			Some((bound, at)) if bound != typ => {
+				let graph = match scope {
+					Some(graph) => self.nmap.key(graph).clone(),
+					None => "the program".to_string(),
+				};
+				self.diagnostics.push(
+					Diagnostic::error(
+						diagnostic::INCOMPATIBLE_WIDTHS,
+						format!("incompatible widths: {} is {} in {}, found {}", name, bound, graph, typ),
+						span,
+					)
+					.label(format!("this is {}", typ))
+					.secondary(*at, format!("{} is {} from here", name, bound))
+					.note("a wildcard stands for one base type throughout its graph".to_string())
+					.in_module(module(&self.nmap, key)),
+				);
+				false
+			}
+			Some(_) => true,
+			None => {
+				self.bindings.insert((scope, name), (typ.clone(), span));
+				true
+			}
+		}
+	}
+
+	// numeric literals take the width of their suffix or of the type they are
+	// declared with, strings are always u8 arrays
+	fn literal(&mut self, key: &str, element: &Element) -> Type {
+		let literal = match &element.literal {
+			Some(literal) => literal,
+			None => return Type::Unknown,
+		};
+		let typ = match (&literal.width, &literal.value) {
+			(Some(width), _) => width.clone(),
+			(None, Value::Integer(_)) => Type::Integer,
+			(None, Value::Decimal(_)) => Type::Decimal,
+			// strings, and any complex data, are byte arrays
+			(None, Value::Bytes(_)) => return Type::Array(Box::new(Type::Base(Format::Unsigned, 8))),
+		};
+		let (context, declared) = match &self.context {
+			Some(context) => context.clone(),
+			None => {
+				self.fits(key, element, &literal.value, &typ);
+				return typ;
+			}
+		};
+		match unify(&context, &typ) {
+			Some(typ) => {
+				self.fits(key, element, &literal.value, &typ);
+				typ
+			}
+			None => {
+				self.diagnostics.push(
+					Diagnostic::error(
+						diagnostic::TYPE_MISMATCH,
+						format!("mismatched types: expected {}, found {}", context, typ),
+						element.meta,
+					)
+					.label(format!("{} cannot be {}", element.text, context))
+					.secondary(declared, format!("declared {} here", context))
+					.in_module(module(&self.nmap, key)),
+				);
+				Type::Unknown
+			}
+		}
+	}
+
+	// a literal has to fit the base type it ends up with
+	fn fits(&mut self, key: &str, element: &Element, value: &Value, typ: &Type) {
+		let (format, width) = match typ {
+			Type::Base(format, width) => (*format, *width),
+			_ => return,
+		};
+		// complex numbers are two floats of half the width
+		let float = match format {
+			Format::Complex => width / 2,
+			_ => width,
+		};
+		let overflow = match (value, format) {
+			(Value::Integer(v), Format::Signed) => *v > (1u64 << (width - 1)) - 1,
+			(Value::Integer(v), Format::Unsigned) => width < 64 && *v >= 1u64 << width,
+			(Value::Decimal(v), Format::Float | Format::Complex) => float == 32 && *v > f32::MAX as f64,
+			_ => false,
+		};
+		if overflow {
+			self.diagnostics.push(
+				Diagnostic::error(
+					diagnostic::LITERAL_OVERFLOW,
+					format!("literal out of range for {}", typ),
+					element.meta,
+				)
+				.label(format!("{} does not fit in {}", element.text, typ))
+				.note(format!("{} holds {}", typ, range(format, width)))
+				.in_module(module(&self.nmap, key)),
+			);
+			return;
+		}
+
+		// the nearest value the width can hold
+		let nearest = match (value, format, float) {
+			(Value::Integer(v), Format::Float | Format::Complex, 32) => (*v as f32 as f64 != *v as f64)
+				.then(|| format!("{}", *v as f32)),
+			(Value::Integer(v), Format::Float | Format::Complex, _) => (*v as f64 as u64 != *v)
+				.then(|| format!("{}", *v as f64)),
+			(Value::Decimal(v), Format::Float | Format::Complex, 32) => {
+				let nearest = (*v as f32).to_string();
+				(nearest.parse::<f64>() != Ok(*v)).then_some(nearest)
+			}
+			_ => None,
+		};
+		if let Some(nearest) = nearest {
+			self.diagnostics.push(
+				Diagnostic::warning(
+					diagnostic::PRECISION_LOSS,
+					format!("{} loses precision as {}", element.text, typ),
+					element.meta,
+				)
+				.label(format!("becomes {}", nearest))
+				.in_module(module(&self.nmap, key)),
+			);
+		}
+	}
+
+	fn push(&mut self, key: &String, element: &mut Element, stack: &mut Stack) {
+		let typ = match element.kind {
+			Kind::Integer | Kind::Decimal | Kind::String => self.literal(key, element),
+			Kind::Para => self.parameters.get(&element.text).cloned().unwrap_or(Type::Unknown),
+			Kind::Term | Kind::Pun => match self.reference(&element.path) {
+				// a function point is applied to the values on the stack
+				Some(Type::Function(inputs, outputs)) => {
+					let values = stack.pop(inputs.len(), element);
+					self.arguments(key, element, &inputs, &values);
+					self.apply(element, Type::Function(inputs, outputs), stack);
+					return;
+				}
+				Some(typ) => typ,
+				None => match builtin::operator(&element.path) {
+					Some(operator) => {
+						let values = stack.pop(operator.inputs, element);
+						self.operands(key, element, operator, &values);
+						let inputs = types(&values);
+						let outputs = operate(operator, &inputs);
+						self.apply(element, Type::Function(inputs, outputs), stack);
+						return;
+					}
+					None => {
+						// typed through a cycle, its effect on the stack is not known
+						stack.known = false;
+						return;
+					}
+				},
+			},
+			Kind::Tuple => {
+				let inner = self.stack(key, &mut element.body);
+				if inner.known {
+					inner.effect()
+				} else {
+					Type::Unknown
+				}
+			}
+			Kind::Array => {
+				let inner = self.stack(key, &mut element.body);
+				let items = inner.types();
+				match items.first() {
+					Some(first) if inner.known && items.iter().all(|t| t == first) => {
+						Type::Array(Box::new(first.clone()))
+					}
+					_ => Type::Array(Box::new(Type::Unknown)),
+				}
+			}
+			// parameters are on the stack before the body
+			Kind::Function => {
+				let outer = self.parameters.clone();
+				for parameter in &element.head {
+					self.parameters.remove(&parameter.text);
+				}
+				let inner = self.stack(key, &mut element.body);
+				self.parameters = outer;
+				let inputs = vec![Type::Unknown; element.head.len() + inner.consumed];
+				let outputs = if inner.known { inner.types() } else { vec![Type::Unknown] };
+				Type::Function(inputs, outputs)
+			}
+			_ => Type::Unknown,
+		};
+		element.typ = typ.clone();
+		stack.values.push((typ, element.meta));
+	}
+
+	fn apply(&mut self, element: &mut Element, effect: Type, stack: &mut Stack) {
+		if let Type::Function(_, outputs) = &effect {
+			for output in outputs {
+				stack.values.push((output.clone(), element.meta));
+			}
+		}
+		element.typ = effect;
+	}
+
+	fn stack(&mut self, key: &String, elements: &mut [Element]) -> Stack {
+		let mut stack = Stack::new();
+		for element in elements.iter_mut() {
+			self.push(key, element, &mut stack);
+		}
+		stack
+	}
+
+	// the type of the network a resolved term names, None for operators and
+	// points that are being typed
+	fn reference(&mut self, path: &String) -> Option<Type> {
+		let index = self.nmap.index(path)?;
+		if self.nmap.at(index).node == Node::Point {
+			self.point(path)
+		} else {
+			Some(Type::Unknown)
+		}
+	}
+
+	// arithmetic and comparisons take values of one base type
+	fn operands(&mut self, key: &str, element: &Element, operator: &Operator, values: &[(Type, Span)]) {
+		if operator.class != Class::Arithmetic && operator.class != Class::Comparison {
+			return;
+		}
+		if let [(a, left), (b, right)] = values {
+			if a.is_numeric() && b.is_numeric() && join(a, b).is_none() {
+				self.diagnostics.push(
+					Diagnostic::error(
+						diagnostic::INCOMPATIBLE_WIDTHS,
+						format!("incompatible widths: {} takes {} and {}", element.text, a, b),
+						element.meta,
+					)
+					.label(format!("{} and {} cannot be mixed", a, b))
+					.secondary(*left, format!("this is {}", a))
+					.secondary(*right, format!("this is {}", b))
+					.in_module(module(&self.nmap, key)),
+				);
+			}
+		}
+	}
+
+	// the values a function point takes against the inputs it declares
+	fn arguments(&mut self, key: &str, element: &Element, inputs: &[Type], values: &[(Type, Span)]) {
+		for (expected, (found, span)) in inputs.iter().zip(values) {
+			if !compatible(expected, found) {
+				self.diagnostics.push(
+					Diagnostic::error(
+						diagnostic::TYPE_MISMATCH,
+						format!("mismatched types: expected {}, found {}", expected, found),
+						*span,
+					)
+					.label(format!("this is {}", found))
+					.secondary(element.meta, format!("{} takes {} here", element.text, expected))
+					.in_module(module(&self.nmap, key)),
+				);
+			}
+		}
+	}
+
+	// the number of values the stack takes and leaves against the signature
+	fn effect(&mut self, key: &str, signature: &Signature, stack: &Stack, span: Span) {
+		let name = key.rsplit('.').next().unwrap();
+		if let Some(underflow) = &stack.underflow {
+			self.diagnostics.push(
+				Diagnostic::error(
+					diagnostic::STACK_UNDERFLOW,
+					format!(
+						"stack underflow: {} takes {}, the stack holds {}",
+						underflow.name,
+						values(underflow.takes),
+						underflow.holds
+					),
+					underflow.span,
+				)
+				.label(format!("{} missing", values(underflow.takes - underflow.holds)))
+				.secondary(signature.span, format!("{} takes {}", name, values(signature.inputs.len())))
+				.in_module(module(&self.nmap, key)),
+			);
+		}
+
+		let (found, declared) = (stack.values.len(), signature.outputs.len());
+		if found > declared {
+			let extra = found - declared;
+			self.diagnostics.push(
+				Diagnostic::error(
+					diagnostic::LEFTOVER_VALUES,
+					format!("{} leaves {} on the stack, {} declared", name, values(found), declared),
+					stack.values[0].1,
+				)
+				.label(format!("{} left over", values(extra)))
+				.secondary(signature.span, format!("{} leaves {}", name, values(declared)))
+				.note("every value a point pushes must be taken or declared".to_string())
+				.in_module(module(&self.nmap, key)),
+			);
+		} else if found < declared {
+			self.diagnostics.push(
+				Diagnostic::error(
+					diagnostic::MISSING_VALUES,
+					format!("{} leaves {} on the stack, {} declared", name, values(found), declared),
+					span,
+				)
+				.label(format!("{} missing", values(declared - found)))
+				.secondary(signature.span, format!("{} leaves {}", name, values(declared)))
+				.in_module(module(&self.nmap, key)),
+			);
+		}
+	}
+
+	// the values on top of the stack against the declared outputs
+	fn check(&mut self, key: &str, scope: Option<Index>, signature: &Signature, stack: &Stack) {
+		let name = key.rsplit('.').next().unwrap();
+		for ((expected, declared), (found, span)) in signature.outputs.iter().rev().zip(stack.values.iter().rev()) {
+			if let (true, Some(typ @ Type::Base(..))) = (expected.is_wildcard(), unify(expected, found)) {
+				if !self.bind(key, scope, expected, &typ, *span) {
+					continue;
+				}
+			}
+			let expected = &self.resolve(scope, expected);
+			if !compatible(expected, found) {
+				self.diagnostics.push(
+					Diagnostic::error(
+						diagnostic::TYPE_MISMATCH,
+						format!("mismatched types: expected {}, found {}", expected, found),
+						*span,
+					)
+					.label(format!("this is {}", found))
+					.secondary(*declared, format!("{} is declared {} here", name, expected))
+					.in_module(module(&self.nmap, key)),
+				);
+			}
+		}
+	}
+
+	// a function body with more parameters than the point declares inputs
+	fn arity(&mut self, key: &str, signature: &Signature, head: &[Element]) {
+		let name = key.rsplit('.').next().unwrap();
+		let extra = &head[signature.inputs.len()..];
+		let span = Span::new(extra[0].meta.start, extra[extra.len() - 1].meta.end);
+		self.diagnostics.push(
+			Diagnostic::error(
+				diagnostic::STACK_UNDERFLOW,
+				format!(
+					"stack underflow: the function takes {}, {} takes {}",
+					values(head.len()),
+					name,
+					values(signature.inputs.len())
+				),
+				span,
+			)
+			.label(format!("{} missing", values(extra.len())))
+			.secondary(signature.span, format!("{} takes {}", name, values(signature.inputs.len())))
+			.in_module(module(&self.nmap, key)),
+		);
+	}
+
+	// every value of a point in a typed graph against the graph's type
+	fn constrain(&mut self, key: &str, graph: Option<Index>, typ: &Type, declared: Span, stack: &Stack) {
+		for (found, span) in &stack.values {
+			if let (true, Some(base @ Type::Base(..))) = (typ.is_wildcard(), unify(typ, found)) {
+				if !self.bind(key, graph, typ, &base, *span) {
+					continue;
+				}
+			}
+			let expected = &self.resolve(graph, typ);
+			if !compatible(expected, found) {
+				let name = graph.map(|g| self.nmap.at(g).name.to_string()).unwrap_or_default();
+				self.diagnostics.push(
+					Diagnostic::error(
+						diagnostic::TYPE_MISMATCH,
+						format!("mismatched types: expected {}, found {}", expected, found),
+						*span,
+					)
+					.label(format!("this is {}", found))
+					.secondary(declared, format!("{} is declared {} here", name, expected))
+					.in_module(module(&self.nmap, key)),
+				);
+			}
+		}
+	}
+}
+
+// base type names in a signature that are not a base type
+fn widths(module: &str, element: &Element, diagnostics: &mut Vec<Diagnostic>) {
+	if (element.kind == Kind::Term || element.kind == Kind::Para) && malformed(&element.text) {
+		diagnostics.push(
+			Diagnostic::error(
+				diagnostic::UNKNOWN_WIDTH,
+				format!("unknown base type {}", element.text),
+				element.meta,
+			)
+			.label("not a base type".to_string())
+			.note("the base types are i8 i16 i32 i64, u8 u16 u32 u64, f32 f64 and c64".to_string())
+			.in_module(module),
+		);
+	}
+	for child in element.head.iter().chain(element.body.iter()) {
+		widths(module, child, diagnostics);
+	}
+}
+
+// `op: |i32 i32| i32` is the same signature as `op: i32 i32 -> i32`
+fn signature(element: &Element) -> Signature {
+	let (head, body) = match element.body.as_slice() {
+		[function] if element.head.is_empty() && function.kind == Kind::Function => {
+			(&function.head, &function.body)
+		}
+		_ => (&element.head, &element.body),
+	};
+	Signature {
+		inputs: head.iter().map(|e| (declared(e), e.meta)).collect(),
+		outputs: body.iter().map(|e| (declared(e), e.meta)).collect(),
+		span: element.meta,
+	}
+}
+
+// the type an element of a signature names, `|i32 i32| i32` is a function
+fn declared(element: &Element) -> Type {
+	match element.kind {
+		Kind::Term | Kind::Para => base(&element.text).unwrap_or_else(|| Type::Named(element.text.clone())),
+		Kind::Tuple => single(element.body.iter().map(declared).collect()),
+		Kind::Array => match element.body.first() {
+			Some(item) => Type::Array(Box::new(declared(item))),
+			None => Type::Array(Box::new(Type::Unknown)),
+		},
+		Kind::Function => Type::Function(
+			element.head.iter().map(declared).collect(),
+			element.body.iter().map(declared).collect(),
+		),
+		_ => Type::Unknown,
+	}
+}
+
+fn types(values: &[(Type, Span)]) -> Vec<Type> {
+	values.iter().map(|(t, _)| t.clone()).collect()
+}
+
+// what an operator leaves on the stack for the values it took
+fn operate(operator: &Operator, inputs: &[Type]) -> Vec<Type> {
+	match (operator.name, inputs) {
+		("dup", [a]) => vec![a.clone(), a.clone()],
+		("swap", [a, b]) => vec![b.clone(), a.clone()],
+		("over", [a, b]) => vec![a.clone(), b.clone(), a.clone()],
+		("iota", [n]) => vec![Type::Array(Box::new(n.clone()))],
+		(_, [a]) if operator.class == Class::Arithmetic => vec![a.clone()],
+		(_, [a, b]) if operator.class == Class::Arithmetic => {
+			vec![join(a, b).unwrap_or(Type::Unknown)]
+		}
+		_ => vec![Type::Unknown; operator.outputs],
+	}
+}
+
+// the type of arithmetic on two values, arrays work on every item
+fn join(a: &Type, b: &Type) -> Option<Type> {
+	match (a, b) {
+		(Type::Array(a), Type::Array(b)) => Some(Type::Array(Box::new(join(a, b)?))),
+		(Type::Array(a), b) | (b, Type::Array(a)) => Some(Type::Array(Box::new(join(a, b)?))),
+		(a, b) => unify(a, b),
+	}
+}
+
+// `-128 to 127`
+fn range(format: Format, width: usize) -> String {
+	match format {
+		Format::Signed => format!("{} to {}", -(1i128 << (width - 1)), (1i128 << (width - 1)) - 1),
+		Format::Unsigned => format!("0 to {}", (1u128 << width) - 1),
+		_ if width == 32 || format == Format::Complex => format!("up to {:e}", f32::MAX),
+		_ => format!("up to {:e}", f64::MAX),
+	}
+}
+
+// `1 value`, `2 values`
+fn values(count: usize) -> String {
+	format!("{} value{}", count, if count == 1 { "" } else { "s" })
+}
+
+// one value is its own type, several are a tuple
+fn single(mut types: Vec<Type>) -> Type {
+	match types.len() {
+		0 => Type::Unknown,
+		1 => types.pop().unwrap(),
+		_ => Type::Tuple(types),
+	}
+}
+
+// whether a value of type `found` can be used where `expected` is declared
+fn compatible(expected: &Type, found: &Type) -> bool {
+	unify(expected, found).is_some()
+}
+
+// the type a value of both types has, None when no value can be both
+fn unify(a: &Type, b: &Type) -> Option<Type> {
+	match (a, b) {
+		(Type::Unknown, t) | (t, Type::Unknown) => Some(t.clone()),
+		(a, b) if a == b => Some(a.clone()),
+		(Type::Array(a), Type::Array(b)) => Some(Type::Array(Box::new(unify(a, b)?))),
+		(Type::Tuple(a), Type::Tuple(b)) => Some(Type::Tuple(all(a, b)?)),
+		(Type::Function(a, b), Type::Function(c, d)) => Some(Type::Function(all(a, c)?, all(b, d)?)),
+		(Type::Integer, Type::Decimal) | (Type::Decimal, Type::Integer) => Some(Type::Decimal),
+		// a literal takes the type it is used as, a decimal needs a fraction
+		(t, Type::Integer) | (Type::Integer, t) if t.is_base() || matches!(t, Type::Named(_)) => {
+			Some(t.clone())
+		}
+		(t, Type::Decimal) | (Type::Decimal, t) if t.is_fractional() => Some(t.clone()),
+		(Type::Base(f, w), t) | (t, Type::Base(f, w)) if t.admits(*f, *w) => Some(Type::Base(*f, *w)),
+		(Type::Width(w), Type::Format(f)) | (Type::Format(f), Type::Width(w)) if f.widths().contains(w) => {
+			Some(Type::Base(*f, *w))
+		}
+		_ => None,
+	}
+}
+
+fn all(a: &[Type], b: &[Type]) -> Option<Vec<Type>> {
+	if a.len() != b.len() {
+		return None;
+	}
+	a.iter().zip(b).map(|(a, b)| unify(a, b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::{clone, deref, parser, tokenizer};
+	use super::*;
+
+	// the codes of every diagnostic the stages up to the typer report
+	fn codes(source: &str) -> Vec<&'static str> {
+		diagnostics(source).iter().map(|d| d.code).collect()
+	}
+
+	fn diagnostics(source: &str) -> Vec<Diagnostic> {
+		let (tokens, mut diagnostics) = tokenizer::tokenizer(source);
+		let (nmap, emap, tmap, errors) = parser::parser("test", tokens, false);
+		diagnostics.extend(errors);
+		let (nmap, emap, tmap, errors) = clone::parser(nmap, emap, tmap);
+		diagnostics.extend(errors);
+		let (nmap, emap, tmap, errors) = deref::parser(nmap, emap, tmap);
+		diagnostics.extend(errors);
+		let (_, _, _, errors) = parser(nmap, emap, tmap);
+		diagnostics.extend(errors);
+		diagnostics
+	}
+
+	#[test]
+	fn function_body_takes_the_declared_inputs() {
+		assert_eq!(codes("add: i32 i32 -> i32, |x y| x y +\n"), Vec::<&str>::new());
+		assert_eq!(codes("add: |i32 i32| i32, |x y| x y +\n"), Vec::<&str>::new());
+		assert_eq!(codes("inc: i32 i32 -> i32, |x| x +\n"), Vec::<&str>::new());
+	}
+
+	#[test]
+	fn function_body_against_its_signature() {
+		assert_eq!(codes("add: i32 -> f32, |x| x\n"), vec![diagnostic::TYPE_MISMATCH]);
+		assert_eq!(codes("add: i32 i32 -> i32, |x y| x y + 1\n"), vec![diagnostic::LEFTOVER_VALUES]);
+		assert_eq!(codes("add: i32 -> i32, |x y| x y +\n"), vec![diagnostic::STACK_UNDERFLOW]);
+	}
+
+	#[test]
+	fn overrides_rebind_the_copy() {
+		assert_eq!(codes("base.{ a; 1  b: i32, a }\nr: base { a; 2 }\n"), Vec::<&str>::new());
+		assert_eq!(codes("base.{ a; 1  b: i32, a }\nr: base { a; 2.5 }\n"), vec![diagnostic::TYPE_MISMATCH]);
+		assert_eq!(codes("base.{ a; 1  b: i32, base.a }\nr: base { a; 2.5 }\n"), vec![diagnostic::TYPE_MISMATCH]);
+	}
+
+	#[test]
+	fn overrides_name_keys_of_the_source() {
+		assert_eq!(codes("base.{ a; 1 }\nr: base { c; 2 }\n"), vec![diagnostic::UNKNOWN_OVERRIDE]);
+	}
+
+	#[test]
+	fn clone_cycles() {
+		assert_eq!(codes("a: b { }\nb: a { }\n"), vec![diagnostic::CLONE_CYCLE]);
+		assert_eq!(codes("a.{ x; 1  b: a { } }\n"), vec![diagnostic::CLONE_CYCLE]);
+	}
+
+	#[test]
+	fn points_are_not_graphs() {
+		assert_eq!(codes("p; 1\nr: p { }\n"), vec![diagnostic::CLONE_POINT]);
+		assert_eq!(codes("p; 1\nq; p.x\n"), vec![diagnostic::NOT_A_GRAPH]);
+	}
+
+	#[test]
+	fn copies_resolve_names_where_they_were_written() {
+		let source = "lib.{ k; 2  e.{ y: i32, k } }\nk; 1.5\nmy-e: lib.e { }\n";
+		assert_eq!(codes(source), Vec::<&str>::new());
+		assert_eq!(codes("lib.{ k; 2  e.{ y: i32, k } }\nmy-e: lib.e { }\n"), Vec::<&str>::new());
+	}
+
+	#[test]
+	fn copies_report_where_they_were_written() {
+		let diagnostics = diagnostics("g.{ a; 1  b: i32, a }\nr: g { a; 1.5 }\n");
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].module.as_deref(), Some("test.g.b"));
+	}
+
+	#[test]
+	fn copies_of_typed_graphs_keep_the_type() {
+		let source = "quad: f32, { a; 1  b; a 2 * }\nr: quad { a; 1i64 }\n";
+		assert_eq!(codes(source), vec![diagnostic::TYPE_MISMATCH]);
+		assert_eq!(codes("quad: f32, { a; 1  b; a 2 * }\nr: quad { a; 1.5 }\n"), Vec::<&str>::new());
+	}
+
+	#[test]
+	fn dotted_keys_resolve() {
+		assert_eq!(codes("a.b: i32, 1\nc: i32, a.b\n"), Vec::<&str>::new());
+		assert_eq!(codes("a.b: i32, 1\nc: f32, a.b\n"), vec![diagnostic::TYPE_MISMATCH]);
+	}
+}
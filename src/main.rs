@@ -1,129 +1,472 @@
 #![allow(unused_variables)]
 
+use std::collections::BTreeSet;
 use std::env;
+use std::fmt::Debug;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
 
+mod cli;
 pub mod compiler;
 // use compiler::tokenizer;
-use compiler::{clone, parser, tokenizer};
+use cli::{Command, Dump, Format, Options};
+use compiler::{clone, deref, diagnostic, dot, export, format, loader, map, parser, tokenizer, typer};
+use diagnostic::{Diagnostic, Sources};
+use export::Export;
+use loader::Loader;
+use map::Map;
+use parser::NMap;
 use tokenizer::Token;
 
-// use notify::{raw_watcher, RawEvent, RecursiveMode, Watcher};
-// use std::sync::mpsc::channel;
+use notify::{raw_watcher, RawEvent, RecursiveMode, Watcher};
+
+// how long the watcher waits for a burst of saves to settle before recompiling
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+// files written by the compiler, never treated as sources
+const ARTIFACTS: [&str; 8] = [
+    ".tokens", ".nmap", ".emap", ".tmap", ".errors", ".json", ".sexpr", ".dot",
+];
 
 fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
 
-    if args.len() < 1 {
-        eprintln!("Usage: z.exe <source>");
-        std::process::exit(1);
+    let options = match cli::parse(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("Error: {}\n\n{}", msg, cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if options.command == Command::Watch {
+        watch(&options);
+    }
+    if options.command == Command::Fmt {
+        let failed = format(&options);
+        std::process::exit(if failed > 0 { 1 } else { 0 });
     }
 
     // read_file(&args[0]); // first run
     // compiler(&args[0], &args[1]);
-    let source = &args[0];
-    event_router(notify::op::WRITE, source);
-
-    std::process::exit(0);
-
-    // // Create a channel to receive the events.
-    // let (tx, rx) = channel();
-
-    // // Create a watcher object, delivering raw events.
-    // // The notification back-end is selected based on the platform.
-    // let mut watcher = raw_watcher(tx).unwrap();
-
-    // // Add a path to be watched. All files and directories at that path and
-    // // below will be monitored for changes.
-    // watcher.watch(source, RecursiveMode::Recursive).unwrap();
-
-    // loop {
-    // 	match rx.recv() {
-    // 		Ok(RawEvent {
-    // 			path: Some(path),
-    // 			op: Ok(op),
-    // 			cookie,
-    // 		}) => {
-    // 			println!("{:?} {:?} ({:?})", op, path, cookie);
-    // 			event_router(op, source, target);
-    // 		}
-    // 		Ok(event) => println!("broken event: {:?}", event),
-    // 		Err(e) => println!("watch error: {:?}", e),
-    // 	}
-    // }
-}
-
-fn event_router(operation: notify::Op, source: &String) {
-    match operation {
-        notify::op::WRITE => {
-            let msg = match compile(source) {
-                Ok(msg) => msg,
-                Err(msg) => msg,
-            };
-            let error_path = &mut source.clone();
-            error_path.push_str(&".errors".to_string());
-            write_file(error_path, &msg);
+    let source = Path::new(&options.source);
+    let dir = source.parent().unwrap_or(Path::new(""));
+    let root = root_name(&options, source, dir);
+    let mut loader = Loader::new(search_path(&options, dir), options.shadowing);
+    let errors = event_router(notify::op::WRITE, &options.source, &root, &options, &mut loader);
+
+    std::process::exit(if errors > 0 { 1 } else { 0 });
+}
+
+fn watch(options: &Options) {
+    let target = &options.source;
+    let target_path = Path::new(target);
+    let is_dir = target_path.is_dir();
+    if !is_dir && !target_path.is_file() {
+        eprintln!("Error: '{}' is neither a file nor a directory", target);
+        std::process::exit(1);
+    }
+
+    // Create a channel to receive the events.
+    let (tx, rx) = channel();
+
+    // Create a watcher object, delivering raw events.
+    // The notification back-end is selected based on the platform.
+    let mut watcher = raw_watcher(tx).unwrap();
+
+    // Editors often save by replacing the file, which drops a watch on the
+    // file itself, so a single source is watched through its directory.
+    let file = fs::canonicalize(target_path).unwrap();
+    let watched = if is_dir {
+        file.clone()
+    } else {
+        file.parent().unwrap().to_path_buf()
+    };
+
+    // imports live anywhere below the search path, which starts with the
+    // --path directories and ends with the watched one
+    let search = search_path(options, &watched);
+    let mut dirs: Vec<PathBuf> = search.iter().map(|d| fs::canonicalize(d).unwrap_or(d.clone())).collect();
+    dirs.sort();
+    dirs.dedup();
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if roots.iter().any(|root| dir.starts_with(root)) {
+            continue;
+        }
+        if let Err(e) = watcher.watch(&dir, RecursiveMode::Recursive) {
+            if watched.starts_with(&dir) {
+                eprintln!("Error: failed to watch '{}': {:?}", target, e);
+                std::process::exit(1);
+            }
+            eprintln!("Warning: failed to watch '{}': {:?}", dir.display(), e);
+        }
+        roots.push(dir);
+    }
+
+    // kept for the whole session so unchanged imports are not reparsed
+    let mut loader = Loader::new(search, options.shadowing);
+
+    let mut sources = Vec::new();
+    if is_dir {
+        find_sources(&file, &mut sources);
+    } else {
+        sources.push(file.clone());
+    }
+    for source in sources {
+        let root = root_name(options, &source, &watched);
+        let source = source.to_string_lossy().to_string();
+        event_router(notify::op::WRITE, &source, &root, options, &mut loader);
+    }
+    println!("watching {}", target);
+
+    loop {
+        // block for the first event, then drain the burst that follows it
+        let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+        let mut event = rx.recv().map_err(|_| RecvTimeoutError::Disconnected);
+        loop {
+            match event {
+                Ok(RawEvent {
+                    path: Some(path),
+                    op: Ok(op),
+                    ..
+                }) => {
+                    if !is_source(&path) {
+                        // artifacts and editor files
+                    } else if (is_dir && path.starts_with(&watched)) || path == file {
+                        pending.insert(path);
+                    } else {
+                        // an import of the watched file
+                        pending.extend(loader.dependents(&path));
+                    }
+                }
+                Ok(event) => println!("broken event: {:?}", event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    eprintln!("Error: file watcher stopped");
+                    std::process::exit(1);
+                }
+            }
+            event = rx.recv_timeout(DEBOUNCE);
+        }
+
+        // sources that import a changed file are stale too
+        for path in pending.clone() {
+            if is_dir {
+                pending.extend(loader.dependents(&path));
+            }
+        }
+
+        for path in pending {
+            // deleted or renamed away
+            if path.is_file() {
+                let root = root_name(options, &path, &watched);
+                let source = path.to_string_lossy().to_string();
+                event_router(notify::op::WRITE, &source, &root, options, &mut loader);
+            }
+        }
+    }
+}
+
+// returns the number of sources that could not be formatted or, with --check,
+// are not formatted
+fn format(options: &Options) -> usize {
+    let target = Path::new(&options.source);
+    let mut sources = Vec::new();
+    if target.is_dir() {
+        find_sources(target, &mut sources);
+        sources.sort();
+    } else {
+        sources.push(target.to_path_buf());
+    }
+
+    let mut failed = 0;
+    for path in sources {
+        let source = path.to_string_lossy().to_string();
+        let input = match read_file(&source, options) {
+            Some(input) => input,
+            None => continue,
+        };
+        let formatted = match format::format(&input) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                let sources = Sources::new(&module_name(&path, Path::new("")), &source, &input);
+                eprint!("{}", diagnostic::render(&diagnostics, &sources));
+                failed += 1;
+                continue;
+            }
+        };
+        if options.stdout {
+            print!("{}", formatted);
+        } else if formatted == input {
+            // already formatted
+        } else if options.check {
+            println!("not formatted: {}", source);
+            failed += 1;
+        } else {
+            if write_file(&source, &formatted, options) {
+                println!("formatted {}", source);
+            }
+        }
+    }
+    failed
+}
+
+// --root names a single source, or prefixes every module of a watched directory
+fn root_name(options: &Options, source: &Path, base: &Path) -> String {
+    let module = module_name(source, base);
+    match &options.root {
+        Some(root) if Path::new(&options.source).is_dir() => format!("{}.{}", root, module),
+        Some(root) => root.clone(),
+        None => module,
+    }
+}
+
+// the path of a source below `base` without its extension, so that
+// `library/windowing.z` in the project directory becomes `library.windowing`
+fn module_name(source: &Path, base: &Path) -> String {
+    let relative = source.strip_prefix(base).unwrap_or(source);
+    let mut segments: Vec<String> = Vec::new();
+    if let Some(parent) = relative.parent() {
+        for component in parent.components() {
+            if let std::path::Component::Normal(name) = component {
+                segments.push(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    if let Some(stem) = relative.file_stem() {
+        segments.push(stem.to_string_lossy().to_string());
+    }
+    segments
+        .iter()
+        .map(|segment| segment.replace(|c: char| c == '.' || c.is_whitespace(), "-"))
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+// directories imports are looked up in, --path entries before the source's own
+fn search_path(options: &Options, dir: &Path) -> Vec<PathBuf> {
+    let mut search: Vec<PathBuf> = options.path.iter().map(PathBuf::from).collect();
+    search.push(dir.to_path_buf());
+    search
+}
+
+fn find_sources(dir: &Path, sources: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_sources(&path, sources);
+        } else if is_source(&path) {
+            sources.push(path);
         }
-        _ => {}
+    }
+}
+
+fn is_source(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
     };
+    // hidden files and editor swap/backup files
+    if name.starts_with('.') || name.ends_with('~') || name.ends_with(".swp") {
+        return false;
+    }
+    !ARTIFACTS.iter().any(|artifact| name.ends_with(artifact))
+}
+
+// returns the number of errors found in the source
+fn event_router(
+    operation: notify::Op,
+    source: &String,
+    root: &str,
+    options: &Options,
+    loader: &mut Loader,
+) -> usize {
+    if operation != notify::op::WRITE {
+        return 0;
+    }
+    let input = match read_file(source, options) {
+        Some(input) => input,
+        None => return 1,
+    };
+    let mut sources = Sources::new(root, source, &input);
+    let diagnostics = compile(source, root, &input, options, loader, &mut sources);
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    let msg = if diagnostics.is_empty() {
+        "no errors".to_string()
+    } else {
+        diagnostic::render(&diagnostics, &sources)
+    };
+
+    if options.stdout || options.command == Command::Check {
+        if !diagnostics.is_empty() {
+            eprint!("{}", msg);
+        }
+    } else {
+        write_output(source, ".errors", &msg, options);
+        println!("compiled {}: {} error(s)", source, errors);
+    }
+    errors
 }
 
-fn compile(source: &String) -> Result<String, String> {
-    let input = read_file(source);
+fn compile(
+    source: &String,
+    root: &str,
+    input: &str,
+    options: &Options,
+    loader: &mut Loader,
+    sources: &mut Sources,
+) -> Vec<Diagnostic> {
+    let emits = |dump: Dump| options.emit.contains(&dump);
     //
     //
-    let tokens = tokenizer::tokenizer(&input)?;
-    let token_path = &mut source.clone();
-    token_path.push_str(&".tokens".to_string());
-    write_file(token_path, &token_string(&tokens));
+    let (tokens, mut diagnostics) = tokenizer::tokenizer(input);
+    if emits(Dump::Tokens) {
+        if options.format == Format::Debug {
+            write_output(source, ".tokens", &token_string(&tokens), options);
+        } else {
+            write_dump(source, ".tokens", &tokens, options);
+        }
+    }
+    if !options.runs(Dump::Parse) {
+        return diagnostics;
+    }
+    //
+    //
+    let (mut nmap, mut emap, mut tmap, mut errors) = parser::parser(root, tokens, options.shadowing);
+    diagnostics.append(&mut errors);
+    let path = Path::new(source);
+    let mut errors = loader.load(path, root, &mut nmap, &mut emap, &mut tmap, sources);
+    diagnostics.append(&mut errors);
+    if emits(Dump::Parse) {
+        dump_nmap(source, ".1.nmap", &nmap, options);
+        dump(source, ".1.emap", &emap, options);
+        dump(source, ".1.tmap", &tmap, options);
+    }
+    // later stages would only report the fallout of these errors
+    if !options.runs(Dump::Clone) || diagnostics.iter().any(|d| d.is_error()) {
+        return diagnostics;
+    }
+    //
+    //
+    let (nmap, emap, tmap, mut errors) = clone::parser(nmap, emap, tmap);
+    diagnostics.append(&mut errors);
+    if emits(Dump::Clone) {
+        dump_nmap(source, ".2.nmap", &nmap, options);
+        dump(source, ".2.emap", &emap, options);
+        dump(source, ".2.tmap", &tmap, options);
+    }
+    if !options.runs(Dump::Deref) || diagnostics.iter().any(|d| d.is_error()) {
+        return diagnostics;
+    }
     //
     //
-    let (nmap, emap, tmap) = parser::parser(&"filename".to_string(), tokens)?;
-    let nmap_path = &mut source.clone();
-    let emap_path = &mut source.clone();
-    let tmap_path = &mut source.clone();
-    nmap_path.push_str(&".1.nmap".to_string());
-    emap_path.push_str(&".1.emap".to_string());
-    tmap_path.push_str(&".1.tmap".to_string());
-    write_file(nmap_path, &format!("{:#?}", &nmap));
-    write_file(emap_path, &format!("{:#?}", &emap));
-    write_file(tmap_path, &format!("{:#?}", &tmap));
+    let (nmap, emap, tmap, mut errors) = deref::parser(nmap, emap, tmap);
+    diagnostics.append(&mut errors);
+    if emits(Dump::Deref) {
+        dump(source, ".3.emap", &emap, options);
+    }
+    if emits(Dump::Dot) {
+        write_output(source, ".dot", &dot::graph(root, &nmap, &emap), options);
+    }
+    if !options.runs(Dump::Type) || diagnostics.iter().any(|d| d.is_error()) {
+        return diagnostics;
+    }
     //
-    // let (nmap, emap, tmap) = clone::parser(nmap, emap, tmap)?;
-    // let nmap_path = &mut source.clone();
-    // let emap_path = &mut source.clone();
-    // let tmap_path = &mut source.clone();
-    // nmap_path.push_str(&".2.nmap".to_string());
-    // emap_path.push_str(&".2.emap".to_string());
-    // tmap_path.push_str(&".2.tmap".to_string());
-    // write_file(nmap_path, &format!("{:#?}", &nmap));
-    // write_file(emap_path, &format!("{:#?}", &emap));
-    // write_file(tmap_path, &format!("{:#?}", &tmap));
     //
+    let (nmap, emap, tmap, mut errors) = typer::parser(nmap, emap, tmap);
+    diagnostics.append(&mut errors);
+    if emits(Dump::Type) {
+        dump(source, ".4.emap", &emap, options);
+    }
 
-    Ok("no errors".to_string())
+    diagnostics
 }
 
-fn read_file(path: &String) -> String {
+// maps are dumped in source order unless --sorted asks for key order
+fn dump<V: Debug + Clone + Export>(source: &String, suffix: &str, map: &Map<V>, options: &Options) {
+    if options.sorted {
+        write_dump(source, suffix, &map.sorted(), options);
+    } else {
+        write_dump(source, suffix, map, options);
+    }
+}
+
+// sorting moves networks, so their links follow them
+fn dump_nmap(source: &String, suffix: &str, nmap: &NMap, options: &Options) {
+    if options.sorted {
+        write_dump(source, suffix, &parser::sorted(nmap), options);
+    } else {
+        write_dump(source, suffix, nmap, options);
+    }
+}
+
+fn write_dump<T: Debug + Export>(source: &String, suffix: &str, data: &T, options: &Options) {
+    let stage = suffix.trim_start_matches('.');
+    let output = match options.format {
+        Format::Debug => format!("{:#?}", data),
+        Format::Json => export::json(stage, data),
+        Format::Sexpr => export::sexpr(stage, data),
+    };
+    let suffix = format!("{}{}", suffix, options.format.extension());
+    write_output(source, &suffix, &output, options);
+}
+
+// an artifact goes next to its source, into --out-dir, or to stdout
+fn write_output(source: &String, suffix: &str, data: &String, options: &Options) {
+    if options.stdout {
+        println!("==> {}{} <==\n{}", source, suffix, data);
+        return;
+    }
+    let path = match &options.out_dir {
+        Some(dir) => {
+            if let Err(e) = fs::create_dir_all(dir) {
+                io_error(format!("failed to create directory '{}': {:?}", dir, e), options);
+                return;
+            }
+            let name = Path::new(source).file_name().unwrap().to_string_lossy();
+            Path::new(dir).join(format!("{}{}", name, suffix))
+        }
+        None => PathBuf::from(format!("{}{}", source, suffix)),
+    };
+    write_file(&path.to_string_lossy().to_string(), data, options);
+}
+
+fn read_file(path: &String, options: &Options) -> Option<String> {
     match fs::read_to_string(path) {
-        Ok(v) => v,
+        Ok(v) => Some(v),
         Err(e) => {
-            eprintln!("Error: failed to read from the file '{}': {:?}", path, e);
-            std::process::exit(1);
+            io_error(format!("failed to read from the file '{}': {:?}", path, e), options);
+            None
         }
     }
 }
 
-fn write_file(path: &String, data: &String) {
+fn write_file(path: &String, data: &String, options: &Options) -> bool {
     match fs::write(path, data) {
         Ok(_v) => {
             // dbg!(v);
+            true
         }
         Err(e) => {
-            eprintln!("Error: failed to write to file '{}': {:?}", path, e);
-            std::process::exit(1);
+            io_error(format!("failed to write to file '{}': {:?}", path, e), options);
+            false
         }
-    };
+    }
+}
+
+// an IO error ends the run, except in watch mode where the next save may fix it
+fn io_error(msg: String, options: &Options) {
+    eprintln!("Error: {}", msg);
+    if options.command != Command::Watch {
+        std::process::exit(1);
+    }
 }
 
 fn token_string(data: &Vec<Token>) -> String {